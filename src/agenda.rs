@@ -0,0 +1,147 @@
+//! Agenda of tasks, reminders and events, grouped by day.
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime};
+
+use crate::backend::{Event, Task};
+use crate::config::Locale;
+use crate::{escape_html, first_local_instant};
+
+const TASK_ICON: &str = "☑️";
+const REMINDER_ICON: &str = "⏰";
+const EVENT_ICON: &str = "📅";
+
+/// A line of the agenda.
+struct Entry {
+	time: DateTime<Local>,
+	html: String,
+}
+
+/// Render the tasks and events of `days` days starting at `from` as HTML, with a heading per day.
+/// Recurring reminders are listed on every day they occur.
+pub fn render(tasks: &[Task], events: &[Event], from: NaiveDate, days: i64, locale: Locale) -> String {
+	let start = start_of_day(from);
+	let end = start_of_day(from + Duration::days(days));
+	let mut entries = Vec::new();
+	for task in tasks {
+		match &task.recurrence {
+			Some(recurrence) => {
+				let mut time = Some(task.todo_time.max(start));
+				while let Some(occurrence) = time.and_then(|x| recurrence.next_after(x)).filter(|x| *x < end) {
+					entries.push(task_entry(task, occurrence));
+					time = Some(occurrence + Duration::seconds(1));
+				}
+			},
+			None if task.todo_time >= start && task.todo_time < end => entries.push(task_entry(task, task.todo_time)),
+			None => {}
+		}
+	}
+	for event in events {
+		if event.todo_time >= start && event.todo_time < end {
+			entries.push(event_entry(event));
+		}
+	}
+	if entries.is_empty() {
+		return "Nothing planned".to_owned();
+	}
+	entries.sort_by_key(|x| x.time);
+
+	let mut html = String::new();
+	let mut day = None;
+	for entry in entries {
+		let date = entry.time.date().naive_local();
+		if day != Some(date) {
+			if day.is_some() {
+				html += "\n";
+			}
+			html += &format!("<b>{} {}</b>\n", locale.weekday_name(date.weekday()), date.format("%Y-%m-%d"));
+			day = Some(date);
+		}
+		html += &entry.html;
+		html += "\n";
+	}
+	html
+}
+
+/// Midnight, or the end of the DST gap in time zones where clocks skip midnight.
+fn start_of_day(date: NaiveDate) -> DateTime<Local> {
+	first_local_instant(date.and_hms_opt(0, 0, 0).unwrap())
+}
+
+fn task_entry(task: &Task, time: DateTime<Local>) -> Entry {
+	let icon = if task.is_reminder { REMINDER_ICON } else { TASK_ICON };
+	let mut html = icon.to_owned();
+	// tasks without time are due at the start of the day
+	if task.recurrence.is_some() || task.note.label("todoTime").is_some() {
+		html += &format!(" {}", time.format("%H:%M"));
+	}
+	html += &format!(" {}", escape_html(&task.note.title));
+	if let Some(location) = task.note.label("location").filter(|x| !x.is_empty()) {
+		html += &format!(" <i>@ {}</i>", escape_html(location));
+	}
+	for tag in task.note.labels("tag").filter(|x| !x.is_empty()) {
+		html += &format!(" #{}", escape_html(tag));
+	}
+	Entry { time, html }
+}
+
+fn event_entry(event: &Event) -> Entry {
+	let mut html = format!("{} {}", EVENT_ICON, event.todo_time.format("%H:%M"));
+	let end = event.note.label("endTime")
+		.and_then(|x| NaiveDateTime::parse_from_str(x, "%Y-%m-%dT%H:%M:%S").ok());
+	if let Some(end) = end {
+		if end.date() == event.todo_time.date().naive_local() {
+			html += &format!("–{}", end.format("%H:%M"));
+		} else {
+			html += &format!("–{}", end.format("%Y-%m-%d %H:%M"));
+		}
+	}
+	html += &format!(" {}", escape_html(&event.note.title));
+	if let Some(location) = event.note.label("location").filter(|x| !x.is_empty()) {
+		html += &format!(" <i>@ {}</i>", escape_html(location));
+	}
+	Entry { time: event.todo_time, html }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::trilium::{Attribute, Note};
+
+	fn note(title: &str, labels: &[(&str, &str)]) -> Note {
+		let mut note = Note {
+			note_id: title.to_owned(),
+			title: title.to_owned(),
+			..Note::default()
+		};
+		for (name, value) in labels {
+			note.attributes.push(Attribute::label(title, name, value));
+		}
+		note
+	}
+
+	#[test]
+	fn days() {
+		let tasks = [
+			Task::from_note(note("taxes", &[("todoDate", "2021-05-06"), ("tag", "home")]), false).unwrap(),
+			Task::from_note(note("call <mom>", &[("todoDate", "2021-05-05"), ("todoTime", "18:00:00")]), true).unwrap(),
+			Task::from_note(note("later", &[("todoDate", "2021-05-07")]), false).unwrap(),
+		];
+		let events = [Event::from_note(note("dentist", &[("startTime", "2021-05-05T09:30:00"), ("endTime", "2021-05-05T10:00:00"), ("location", "town")])).unwrap()];
+		assert_eq!(
+			render(&tasks, &events, NaiveDate::from_ymd(2021, 5, 5), 2, Locale::En),
+			"<b>We 2021-05-05</b>\n📅 09:30–10:00 dentist <i>@ town</i>\n⏰ 18:00 call &lt;mom&gt;\n\n<b>Th 2021-05-06</b>\n☑️ taxes #home\n"
+		);
+		assert_eq!(render(&tasks, &events, NaiveDate::from_ymd(2021, 5, 8), 1, Locale::En), "Nothing planned");
+	}
+
+	#[test]
+	fn midnight_dst_gap() {
+		// in some time zones (e.g. America/Sao_Paulo in 2018) clocks were set forward at midnight,
+		// the day still starts on that day and not the evening before
+		let date = NaiveDate::from_ymd(2018, 11, 4);
+		assert_eq!(render(&[], &[], date, 1, Locale::En), "Nothing planned");
+		assert_eq!(start_of_day(date).date().naive_local(), date);
+		assert!(start_of_day(date.pred()) < start_of_day(date));
+	}
+}
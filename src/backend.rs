@@ -0,0 +1,717 @@
+//! Storage of notes, tasks and events.
+//!
+//! [`TriliumBackend`] is used by the bot, [`MemoryBackend`] allows running the
+//! message handling and alert logic without a Trilium server.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+
+use std::sync::Mutex;
+
+use crate::{error, ical_parsing, Error};
+use crate::config::Templates;
+use crate::recurrence::Recurrence;
+use crate::source::Source;
+use crate::trilium::{Attribute, AttributeType, CreateAttribute, CreateNote, Note, PatchAttribute, Trilium};
+
+/// A task or reminder that is not done or canceled.
+#[derive(Debug, Clone)]
+pub struct Task {
+	pub note: Note,
+	/// For recurring reminders: the next occurrence.
+	pub todo_time: DateTime<Local>,
+	pub is_reminder: bool,
+	pub recurrence: Option<Recurrence>,
+	/// Whether the note is a daily reminder, its `todoDate` is always today.
+	pub daily: bool,
+}
+
+impl Task {
+	/// Parse the `todoDate` and `todoTime` labels of a task note, or the `recurrence` and `skipUntil` labels
+	/// of a recurring reminder.
+	/// Returns `None` for tasks without date, for done or canceled tasks and for ended recurring reminders.
+	pub fn from_note(note: Note, is_reminder: bool) -> Option<Task> {
+		let mut todo_date = None;
+		let mut todo_time = None;
+		for attribute in &note.attributes {
+			if attribute.r#type != AttributeType::Label {
+				continue;
+			}
+			match &*attribute.name {
+				"todoDate" => todo_date = Some(attribute.value.clone()),
+				"todoTime" => todo_time = Some(attribute.value.clone()),
+				"doneDate" => return None,
+				"canceled" if attribute.value == "true" => return None,
+				_ => {}
+			}
+		}
+		if let Some(recurrence) = note.label("recurrence") {
+			let recurrence: Recurrence = recurrence.parse().ok()?;
+			let mut after = Local::now().with_second(0)?.with_nanosecond(0)?;
+			let skip_until = note.label("skipUntil")
+				.and_then(|x| NaiveDateTime::parse_from_str(x, "%Y-%m-%dT%H:%M:%S").ok())
+				.and_then(|x| Local.from_local_datetime(&x).earliest());
+			if let Some(skip_until) = skip_until {
+				after = after.max(skip_until + Duration::seconds(1));
+			}
+			return Some(Task {
+				todo_time: recurrence.next_after(after)?,
+				note,
+				is_reminder,
+				recurrence: Some(recurrence),
+				daily: false,
+			});
+		}
+		let todo_date = NaiveDate::parse_from_str(&todo_date?, "%Y-%m-%d").ok()?;
+		let (hour, minute, second) = if let Some(todo_time) = todo_time {
+			let parts = todo_time.split(':').collect::<Vec<_>>();
+			(parts.first().and_then(|x| x.parse().ok()).unwrap_or(0), parts.get(1).and_then(|x| x.parse().ok()).unwrap_or(0), parts.get(2).and_then(|x| x.parse().ok()).unwrap_or(0))
+		} else { (0, 0, 0) };
+		let todo_time = Local.from_local_datetime(&todo_date.and_hms_opt(hour, minute, second)?).earliest()?;
+		Some(Task {
+			note,
+			todo_time,
+			is_reminder,
+			recurrence: None,
+			daily: false,
+		})
+	}
+
+	/// Today's occurrence of a daily reminder, which only has a `todoTime` label.
+	pub fn daily(mut note: Note, today: NaiveDate) -> Option<Task> {
+		note.attributes.push(Attribute::label(&note.note_id, "todoDate", &today.format("%Y-%m-%d").to_string()));
+		let mut task = Task::from_note(note, true)?;
+		task.daily = true;
+		Some(task)
+	}
+
+	/// Whether the task is a recurring or daily reminder, which cannot be done, canceled or moved.
+	pub fn repeats(&self) -> bool {
+		self.recurrence.is_some() || self.daily
+	}
+
+	/// Whether the task is due before `now`. Tasks without time are overdue the day after.
+	/// Reminders are never overdue.
+	pub fn is_overdue(&self, now: DateTime<Local>) -> bool {
+		if self.is_reminder {
+			false
+		} else if self.note.label("todoTime").is_some() {
+			self.todo_time < now
+		} else {
+			self.todo_time.date() < now.date()
+		}
+	}
+}
+
+/// A calendar event, as created from an .ics file.
+#[derive(Debug, Clone)]
+pub struct Event {
+	pub note: Note,
+	pub todo_time: DateTime<Local>,
+}
+
+impl Event {
+	/// Parse the `startTime` label of an event note.
+	pub fn from_note(note: Note) -> Option<Event> {
+		let start_time = NaiveDateTime::parse_from_str(note.label("startTime")?, "%Y-%m-%dT%H:%M:%S").ok()?;
+		let todo_time = Local.from_local_datetime(&start_time).earliest()?;
+		Some(Event {
+			note,
+			todo_time
+		})
+	}
+}
+
+/// A task to create, see [`NotesBackend::create_task`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewTask {
+	pub title: String,
+	pub date: Option<NaiveDate>,
+	pub time: Option<NaiveTime>,
+	pub tags: Vec<String>,
+	pub location: Option<String>,
+}
+
+impl NewTask {
+	/// Labels of the task note.
+	fn labels(&self) -> Vec<(&'static str, String)> {
+		let mut labels = Vec::new();
+		if let Some(date) = self.date {
+			labels.push(("todoDate", date.format("%Y-%m-%d").to_string()));
+		}
+		if let Some(time) = self.time {
+			labels.push(("todoTime", time.format("%H:%M:%S").to_string()));
+		}
+		for tag in &self.tags {
+			labels.push(("tag", tag.clone()));
+		}
+		if let Some(location) = &self.location {
+			labels.push(("location", location.clone()));
+		}
+		labels
+	}
+}
+
+/// A file saved as image or file note.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+	pub title: String,
+	pub file_name: String,
+	pub mime: String,
+	pub data: Vec<u8>,
+}
+
+impl Attachment {
+	pub fn is_image(&self) -> bool {
+		self.mime.starts_with("image/")
+	}
+}
+
+#[async_trait]
+pub trait NotesBackend: Send + Sync {
+	/// Create a text note in today's inbox.
+	async fn create_note(&self, title: &str, content: &str) -> Result<(), Error>;
+	/// Create a text note with the labels of its source, see [`TriliumBackend::new`] for where it is created.
+	async fn create_forwarded_note(&self, title: &str, content: &str, source: &Source) -> Result<(), Error>;
+	/// Add a list item (HTML) to today's day note.
+	async fn append_to_journal(&self, item: &str) -> Result<(), Error>;
+	/// Create an image note in the inbox, with the labels of the `source` of forwarded images.
+	async fn create_image(&self, title: &str, mime: &str, data: Vec<u8>, source: Option<&Source>) -> Result<(), Error>;
+	/// Create a file note in the inbox, or a text note with `text` as content and the file as child note.
+	async fn create_file(&self, title: &str, text: Option<&str>, file_name: &str, mime: &str, data: Vec<u8>, source: Option<&Source>) -> Result<(), Error>;
+	/// Create a text note in the inbox with the attachments as child notes.
+	async fn create_album(&self, title: &str, content: &str, attachments: Vec<Attachment>, source: Option<&Source>) -> Result<(), Error>;
+	/// Open tasks (including overdue ones) and reminders due today or later.
+	async fn tasks(&self) -> Result<Vec<Task>, Error>;
+	/// Events starting today or later.
+	async fn events(&self) -> Result<Vec<Event>, Error>;
+	/// Create a task below the note with the `taskTodoRoot` label.
+	async fn create_task(&self, task: &NewTask) -> Result<(), Error>;
+	async fn create_reminder(&self, time: DateTime<Local>, text: &str) -> Result<(), Error>;
+	async fn create_recurring_reminder(&self, recurrence: &Recurrence, text: &str) -> Result<(), Error>;
+	/// Set the value of a label, adding it if the note does not have it yet.
+	async fn set_label(&self, note_id: &str, name: &str, value: &str) -> Result<(), Error>;
+	/// Create an event note with the original .ics file attached.
+	async fn create_event(&self, event: &ical_parsing::Event, file_name: &str, file_data: &str) -> Result<(), Error>;
+}
+
+pub struct TriliumBackend {
+	trilium: Trilium,
+	templates: Templates,
+	group_forwards: bool,
+}
+
+impl TriliumBackend {
+	/// Notes of forwarded messages are created in the inbox, or below a note per source if `group_forwards` is set.
+	pub fn new(trilium: Trilium, templates: Templates, group_forwards: bool) -> Self {
+		TriliumBackend { trilium, templates, group_forwards }
+	}
+
+	/// Today's inbox note, or the note grouping the messages forwarded from `source`.
+	async fn parent_note(&self, source: Option<&Source>) -> Result<String, Error> {
+		let inbox = self.trilium.inbox_note(Local::today().naive_local()).await?;
+		let source = match source {
+			Some(source) if self.group_forwards => source,
+			_ => return Ok(inbox.note_id),
+		};
+		match self.trilium.search(&format!("#sourceGroup = \"{}\"", source.key)).await?.into_iter().next() {
+			Some(note) => Ok(note.note_id),
+			None => {
+				let note = self.trilium.create_note(&CreateNote::text(&inbox.note_id, &source.name, "")).await?.note;
+				self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "sourceGroup", &source.key)).await?;
+				Ok(note.note_id)
+			}
+		}
+	}
+
+	async fn add_source_labels(&self, note_id: &str, source: Option<&Source>) -> Result<(), Error> {
+		for (name, value) in source.map(Source::labels).unwrap_or_default() {
+			self.trilium.create_attribute(&CreateAttribute::label(note_id, name, &value)).await?;
+		}
+		Ok(())
+	}
+
+	/// Create an image note, or a file note for other types, and return its ID.
+	async fn create_attachment(&self, parent_note_id: &str, attachment: Attachment) -> Result<String, Error> {
+		let note = if attachment.is_image() {
+			self.trilium.create_note(&CreateNote::image(parent_note_id, &attachment.title, &attachment.mime)).await?.note
+		} else {
+			let note = self.trilium.create_note(&CreateNote::file(parent_note_id, &attachment.title, &attachment.mime, "")).await?.note;
+			self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "originalFileName", &attachment.file_name)).await?;
+			note
+		};
+		self.trilium.put_note_data(&note.note_id, attachment.data).await?;
+		Ok(note.note_id)
+	}
+
+	/// Find a template note of the imported `notes` directory by its title.
+	async fn find_template(&self, title: &str) -> Result<Note, Error> {
+		self.trilium.search(&format!("note.title = '{}'", title)).await?
+			.into_iter().next()
+			.ok_or_else(|| error(format!("template note '{}' not found", title)))
+	}
+}
+
+#[async_trait]
+impl NotesBackend for TriliumBackend {
+	async fn create_note(&self, title: &str, content: &str) -> Result<(), Error> {
+		let inbox = self.trilium.inbox_note(Local::today().naive_local()).await?;
+		self.trilium.create_note(&CreateNote::text(&inbox.note_id, title, content)).await?;
+		Ok(())
+	}
+
+	async fn create_forwarded_note(&self, title: &str, content: &str, source: &Source) -> Result<(), Error> {
+		let parent = self.parent_note(Some(source)).await?;
+		let note = self.trilium.create_note(&CreateNote::text(&parent, title, content)).await?.note;
+		self.add_source_labels(&note.note_id, Some(source)).await
+	}
+
+	async fn append_to_journal(&self, item: &str) -> Result<(), Error> {
+		let day_note = self.trilium.day_note(Local::today().naive_local()).await?;
+		let content = self.trilium.get_note_content(&day_note.note_id).await?;
+		self.trilium.put_note_content(&day_note.note_id, &append_list_item(&content, item)).await
+	}
+
+	async fn create_image(&self, title: &str, mime: &str, data: Vec<u8>, source: Option<&Source>) -> Result<(), Error> {
+		let parent = self.parent_note(source).await?;
+		let note_id = self.create_attachment(&parent, Attachment {
+			title: title.to_owned(),
+			file_name: title.to_owned(),
+			mime: mime.to_owned(),
+			data,
+		}).await?;
+		self.add_source_labels(&note_id, source).await
+	}
+
+	async fn create_file(&self, title: &str, text: Option<&str>, file_name: &str, mime: &str, data: Vec<u8>, source: Option<&Source>) -> Result<(), Error> {
+		let parent = self.parent_note(source).await?;
+		let attachment = |title: &str| Attachment {
+			title: title.to_owned(),
+			file_name: file_name.to_owned(),
+			mime: mime.to_owned(),
+			data,
+		};
+		// the source labels are added to the note with the text
+		let note_id = match text {
+			Some(text) => {
+				let note = self.trilium.create_note(&CreateNote::text(&parent, title, &crate::escape_html(text))).await?.note;
+				self.create_attachment(&note.note_id, attachment(file_name)).await?;
+				note.note_id
+			},
+			None => self.create_attachment(&parent, attachment(title)).await?,
+		};
+		self.add_source_labels(&note_id, source).await
+	}
+
+	async fn create_album(&self, title: &str, content: &str, attachments: Vec<Attachment>, source: Option<&Source>) -> Result<(), Error> {
+		let parent = self.parent_note(source).await?;
+		let note = self.trilium.create_note(&CreateNote::text(&parent, title, content)).await?.note;
+		self.add_source_labels(&note.note_id, source).await?;
+		for attachment in attachments {
+			self.create_attachment(&note.note_id, attachment).await?;
+		}
+		Ok(())
+	}
+
+	async fn tasks(&self) -> Result<Vec<Task>, Error> {
+		let today = Local::today().format("%Y-%m-%d").to_string();
+		// inherited labels are not returned by ETAPI, so reminders are identified by their template
+		let mut tasks = Vec::new();
+		for note in self.trilium.search(&format!("~template.title = '{}' #todoDate #!doneDate", self.templates.task)).await? {
+			tasks.extend(Task::from_note(note, false));
+		}
+		for note in self.trilium.search(&format!("~template.title = '{}' #todoDate >= '{}'", self.templates.reminder, today)).await? {
+			tasks.extend(Task::from_note(note, true));
+		}
+		for note in self.trilium.search(&format!("~template.title = '{}' #recurrence", self.templates.reminder)).await? {
+			tasks.extend(Task::from_note(note, true));
+		}
+		for note in self.trilium.search(&format!("~template.title = '{}'", self.templates.daily_reminder)).await? {
+			tasks.extend(Task::daily(note, Local::today().naive_local()));
+		}
+		Ok(tasks)
+	}
+
+	async fn events(&self) -> Result<Vec<Event>, Error> {
+		let today = Local::today().format("%Y-%m-%d");
+		let notes = self.trilium.search(&format!("~template.title = '{}' #startTime >= '{}'", self.templates.event, today)).await?;
+		Ok(notes.into_iter().flat_map(Event::from_note).collect())
+	}
+
+	async fn create_task(&self, task: &NewTask) -> Result<(), Error> {
+		let template = self.find_template(&self.templates.task).await?;
+		let todo_root = self.trilium.search("#taskTodoRoot").await?
+			.into_iter().next()
+			.ok_or_else(|| error("note with label taskTodoRoot not found"))?;
+		let note = self.trilium.create_note(&CreateNote::text(&todo_root.note_id, &task.title, "")).await?.note;
+		self.trilium.create_attribute(&CreateAttribute::relation(&note.note_id, "template", &template.note_id)).await?;
+		for (name, value) in task.labels() {
+			self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, name, &value)).await?;
+		}
+		Ok(())
+	}
+
+	async fn create_reminder(&self, time: DateTime<Local>, text: &str) -> Result<(), Error> {
+		let template = self.find_template(&self.templates.reminder).await?;
+		let date = time.date().naive_local();
+		let day_note = self.trilium.day_note(date).await?;
+		let note = self.trilium.create_note(&CreateNote::text(&day_note.note_id, text, "")).await?.note;
+		self.trilium.create_attribute(&CreateAttribute::relation(&note.note_id, "template", &template.note_id)).await?;
+		self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "todoDate", &date.format("%Y-%m-%d").to_string())).await?;
+		self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "todoTime", &time.format("%H:%M:%S").to_string())).await?;
+		Ok(())
+	}
+
+	async fn create_recurring_reminder(&self, recurrence: &Recurrence, text: &str) -> Result<(), Error> {
+		let template = self.find_template(&self.templates.reminder).await?;
+		let day_note = self.trilium.day_note(Local::today().naive_local()).await?;
+		let note = self.trilium.create_note(&CreateNote::text(&day_note.note_id, text, "")).await?.note;
+		self.trilium.create_attribute(&CreateAttribute::relation(&note.note_id, "template", &template.note_id)).await?;
+		self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "recurrence", &recurrence.to_string())).await?;
+		Ok(())
+	}
+
+	async fn set_label(&self, note_id: &str, name: &str, value: &str) -> Result<(), Error> {
+		let note = self.trilium.get_note(note_id).await?;
+		let existing = note.attributes.iter().find(|x| x.r#type == AttributeType::Label && x.name == name);
+		match existing {
+			Some(attribute) => {
+				self.trilium.patch_attribute(&attribute.attribute_id, &PatchAttribute {
+					value: Some(value.to_owned()),
+					position: None,
+				}).await?;
+			},
+			None => {
+				self.trilium.create_attribute(&CreateAttribute::label(note_id, name, value)).await?;
+			}
+		}
+		Ok(())
+	}
+
+	async fn create_event(&self, event: &ical_parsing::Event, file_name: &str, file_data: &str) -> Result<(), Error> {
+		let template = self.find_template(&self.templates.event).await?;
+		let day_note = self.trilium.day_note(event.start.date()).await?;
+		let content = match &event.description_html {
+			Some(html) if !html.is_empty() => html.replace("\\n", "\n"),
+			_ => event.description.replace("\\n", "<br>").replace('\r', ""),
+		};
+		let note = self.trilium.create_note(&CreateNote::text(&day_note.note_id, &event.summary, &content)).await?.note;
+		self.trilium.create_attribute(&CreateAttribute::relation(&note.note_id, "template", &template.note_id)).await?;
+		self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "uid", &event.uid)).await?;
+		self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "location", &event.location)).await?;
+		self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "startTime", &event.start.format("%Y-%m-%dT%H:%M:%S").to_string())).await?;
+		self.trilium.create_attribute(&CreateAttribute::label(&note.note_id, "endTime", &event.end.format("%Y-%m-%dT%H:%M:%S").to_string())).await?;
+		self.trilium.create_note(&CreateNote::file(&note.note_id, file_name, "text/calendar", file_data)).await?;
+		Ok(())
+	}
+}
+
+/// Add a list item to the last list of a note, or a new list if the note does not end with one.
+fn append_list_item(content: &str, item: &str) -> String {
+	let trimmed = content.trim_end();
+	match trimmed.strip_suffix("</ul>") {
+		Some(list) => format!("{}<li>{}</li></ul>", list, item),
+		None => format!("{}<ul><li>{}</li></ul>", trimmed, item),
+	}
+}
+
+/// In-memory backend for tests.
+#[derive(Default)]
+pub struct MemoryBackend {
+	/// Created text notes as (title, content).
+	pub notes: Mutex<Vec<(String, String)>>,
+	/// Content of today's day note.
+	pub journal: Mutex<String>,
+	/// Created image and file notes as (title, MIME type, data).
+	pub files: Mutex<Vec<(String, String, Vec<u8>)>>,
+	/// Titles of the notes of forwarded messages and their source.
+	pub forwarded: Mutex<Vec<(String, Source)>>,
+	/// Task notes and whether they are reminders.
+	pub tasks: Mutex<Vec<(Note, bool)>>,
+	/// Reminders repeated every day, they only have a `todoTime` label.
+	pub daily_reminders: Mutex<Vec<Note>>,
+	pub events: Mutex<Vec<Note>>,
+	next_id: Mutex<usize>,
+}
+
+impl MemoryBackend {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn new_note(&self, title: &str) -> Note {
+		let mut next_id = self.next_id.lock().unwrap();
+		*next_id += 1;
+		Note {
+			note_id: format!("memory{}", *next_id),
+			title: title.to_owned(),
+			r#type: "text".to_owned(),
+			mime: "text/html".to_owned(),
+			..Note::default()
+		}
+	}
+
+	fn add_source(&self, title: &str, source: Option<&Source>) {
+		if let Some(source) = source {
+			self.forwarded.lock().unwrap().push((title.to_owned(), source.clone()));
+		}
+	}
+
+	/// Add a task note with the given labels and return its note ID.
+	pub fn add_task(&self, title: &str, labels: &[(&str, &str)], is_reminder: bool) -> String {
+		let mut note = self.new_note(title);
+		for (name, value) in labels {
+			note.attributes.push(Attribute::label(&note.note_id, name, value));
+		}
+		let note_id = note.note_id.clone();
+		self.tasks.lock().unwrap().push((note, is_reminder));
+		note_id
+	}
+
+	/// Add a daily reminder at the given time (`HH:MM:SS`) and return its note ID.
+	pub fn add_daily_reminder(&self, title: &str, time: &str) -> String {
+		let mut note = self.new_note(title);
+		note.attributes.push(Attribute::label(&note.note_id, "todoTime", time));
+		let note_id = note.note_id.clone();
+		self.daily_reminders.lock().unwrap().push(note);
+		note_id
+	}
+
+	/// Add an event note with the given labels and return its note ID.
+	pub fn add_event(&self, title: &str, labels: &[(&str, &str)]) -> String {
+		let mut note = self.new_note(title);
+		for (name, value) in labels {
+			note.attributes.push(Attribute::label(&note.note_id, name, value));
+		}
+		let note_id = note.note_id.clone();
+		self.events.lock().unwrap().push(note);
+		note_id
+	}
+}
+
+#[async_trait]
+impl NotesBackend for MemoryBackend {
+	async fn create_note(&self, title: &str, content: &str) -> Result<(), Error> {
+		self.notes.lock().unwrap().push((title.to_owned(), content.to_owned()));
+		Ok(())
+	}
+
+	async fn create_forwarded_note(&self, title: &str, content: &str, source: &Source) -> Result<(), Error> {
+		self.add_source(title, Some(source));
+		self.create_note(title, content).await
+	}
+
+	async fn append_to_journal(&self, item: &str) -> Result<(), Error> {
+		let mut journal = self.journal.lock().unwrap();
+		*journal = append_list_item(&journal, item);
+		Ok(())
+	}
+
+	async fn create_image(&self, title: &str, mime: &str, data: Vec<u8>, source: Option<&Source>) -> Result<(), Error> {
+		self.add_source(title, source);
+		self.files.lock().unwrap().push((title.to_owned(), mime.to_owned(), data));
+		Ok(())
+	}
+
+	async fn create_file(&self, title: &str, text: Option<&str>, file_name: &str, mime: &str, data: Vec<u8>, source: Option<&Source>) -> Result<(), Error> {
+		self.add_source(title, source);
+		let title = match text {
+			Some(text) => {
+				self.notes.lock().unwrap().push((title.to_owned(), crate::escape_html(text)));
+				file_name
+			},
+			None => title,
+		};
+		self.files.lock().unwrap().push((title.to_owned(), mime.to_owned(), data));
+		Ok(())
+	}
+
+	async fn create_album(&self, title: &str, content: &str, attachments: Vec<Attachment>, source: Option<&Source>) -> Result<(), Error> {
+		self.add_source(title, source);
+		self.notes.lock().unwrap().push((title.to_owned(), content.to_owned()));
+		self.files.lock().unwrap().extend(attachments.into_iter().map(|x| (x.title, x.mime, x.data)));
+		Ok(())
+	}
+
+	async fn tasks(&self) -> Result<Vec<Task>, Error> {
+		let today = Local::today().naive_local();
+		let mut tasks: Vec<_> = self.tasks.lock().unwrap().iter()
+			.flat_map(|(note, is_reminder)| Task::from_note(note.clone(), *is_reminder))
+			.filter(|task| !task.is_reminder || task.todo_time.date().naive_local() >= today)
+			.collect();
+		for note in self.daily_reminders.lock().unwrap().iter() {
+			tasks.extend(Task::daily(note.clone(), today));
+		}
+		Ok(tasks)
+	}
+
+	async fn events(&self) -> Result<Vec<Event>, Error> {
+		let today = Local::today().naive_local();
+		Ok(self.events.lock().unwrap().iter()
+			.flat_map(|note| Event::from_note(note.clone()))
+			.filter(|event| event.todo_time.date().naive_local() >= today)
+			.collect())
+	}
+
+	async fn create_task(&self, task: &NewTask) -> Result<(), Error> {
+		let labels = task.labels();
+		let labels: Vec<_> = labels.iter().map(|(name, value)| (*name, &**value)).collect();
+		self.add_task(&task.title, &labels, false);
+		Ok(())
+	}
+
+	async fn create_reminder(&self, time: DateTime<Local>, text: &str) -> Result<(), Error> {
+		self.add_task(text, &[
+			("todoDate", &time.format("%Y-%m-%d").to_string()),
+			("todoTime", &time.format("%H:%M:%S").to_string()),
+		], true);
+		Ok(())
+	}
+
+	async fn create_recurring_reminder(&self, recurrence: &Recurrence, text: &str) -> Result<(), Error> {
+		self.add_task(text, &[("recurrence", &recurrence.to_string())], true);
+		Ok(())
+	}
+
+	async fn set_label(&self, note_id: &str, name: &str, value: &str) -> Result<(), Error> {
+		let mut tasks = self.tasks.lock().unwrap();
+		let mut events = self.events.lock().unwrap();
+		let note = tasks.iter_mut().map(|x| &mut x.0).chain(events.iter_mut())
+			.find(|x| x.note_id == note_id)
+			.ok_or_else(|| error(format!("note {} not found", note_id)))?;
+		match note.attributes.iter_mut().find(|x| x.r#type == AttributeType::Label && x.name == name) {
+			Some(attribute) => attribute.value = value.to_owned(),
+			None => note.attributes.push(Attribute::label(note_id, name, value)),
+		}
+		Ok(())
+	}
+
+	async fn create_event(&self, event: &ical_parsing::Event, _file_name: &str, _file_data: &str) -> Result<(), Error> {
+		self.add_event(&event.summary, &[
+			("uid", &event.uid),
+			("location", &event.location),
+			("startTime", &event.start.format("%Y-%m-%dT%H:%M:%S").to_string()),
+			("endTime", &event.end.format("%Y-%m-%dT%H:%M:%S").to_string()),
+		]);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn note(labels: &[(&str, &str)]) -> Note {
+		let mut note = Note {
+			note_id: "n".to_owned(),
+			title: "task".to_owned(),
+			..Note::default()
+		};
+		for (name, value) in labels {
+			note.attributes.push(Attribute::label("n", name, value));
+		}
+		note
+	}
+
+	fn date(days: i64) -> String {
+		(Local::today().naive_local() + Duration::days(days)).format("%Y-%m-%d").to_string()
+	}
+
+	#[test]
+	fn task_from_note() {
+		let task = Task::from_note(note(&[("todoDate", "2021-05-01"), ("todoTime", "14:30:00")]), false).unwrap();
+		assert_eq!(task.todo_time.naive_local(), NaiveDate::from_ymd(2021, 5, 1).and_hms(14, 30, 0));
+		assert!(task.recurrence.is_none());
+		let task = Task::from_note(note(&[("todoDate", "2021-05-01")]), false).unwrap();
+		assert_eq!(task.todo_time.naive_local(), NaiveDate::from_ymd(2021, 5, 1).and_hms(0, 0, 0));
+
+		assert!(Task::from_note(note(&[]), false).is_none());
+		assert!(Task::from_note(note(&[("todoDate", "tomorrow")]), false).is_none());
+		assert!(Task::from_note(note(&[("todoDate", "2021-05-01"), ("doneDate", "2021-05-01")]), false).is_none());
+		assert!(Task::from_note(note(&[("todoDate", "2021-05-01"), ("canceled", "true")]), false).is_none());
+		assert!(Task::from_note(note(&[("todoDate", "2021-05-01"), ("canceled", "false")]), false).is_some());
+	}
+
+	#[test]
+	fn recurring_task_from_note() {
+		let now = Local::now();
+		let task = Task::from_note(note(&[("recurrence", "FREQ=DAILY;BYHOUR=8;BYMINUTE=0")]), true).unwrap();
+		assert!(task.recurrence.is_some());
+		assert!(task.todo_time > now - Duration::minutes(1) && task.todo_time <= now + Duration::days(1));
+		assert_eq!((task.todo_time.hour(), task.todo_time.minute()), (8, 0));
+
+		let skip_until = task.todo_time.format("%Y-%m-%dT%H:%M:%S").to_string();
+		let skipped = Task::from_note(note(&[("recurrence", "FREQ=DAILY;BYHOUR=8;BYMINUTE=0"), ("skipUntil", &skip_until)]), true).unwrap();
+		assert_eq!(skipped.todo_time.date(), task.todo_time.date().succ());
+
+		assert!(Task::from_note(note(&[("recurrence", "FREQ=DAILY;BYHOUR=8;BYMINUTE=0;UNTIL=20200101")]), true).is_none());
+		assert!(Task::from_note(note(&[("recurrence", "every day")]), true).is_none());
+	}
+
+	#[test]
+	fn overdue() {
+		let now = Local::now();
+		let yesterday = Task::from_note(note(&[("todoDate", &date(-1))]), false).unwrap();
+		assert!(yesterday.is_overdue(now));
+		let today = Task::from_note(note(&[("todoDate", &date(0))]), false).unwrap();
+		assert!(!today.is_overdue(now));
+		let reminder = Task::from_note(note(&[("todoDate", &date(-1))]), true).unwrap();
+		assert!(!reminder.is_overdue(now));
+	}
+
+	#[tokio::test]
+	async fn memory_tasks() {
+		let backend = MemoryBackend::new();
+		let open = backend.add_task("open", &[("todoDate", &date(-3))], false);
+		backend.add_task("done", &[("todoDate", &date(1)), ("doneDate", &date(0))], false);
+		backend.add_task("old reminder", &[("todoDate", &date(-1)), ("todoTime", "10:00:00")], true);
+		let reminder = backend.add_task("reminder", &[("todoDate", &date(1)), ("todoTime", "10:00:00")], true);
+		let daily = backend.add_daily_reminder("daily", "07:15:00");
+
+		let tasks = backend.tasks().await.unwrap();
+		let mut ids: Vec<_> = tasks.iter().map(|x| &*x.note.note_id).collect();
+		ids.sort_unstable();
+		let mut expected = vec![&*open, &*reminder, &*daily];
+		expected.sort_unstable();
+		assert_eq!(ids, expected);
+		let daily = tasks.iter().find(|x| x.note.title == "daily").unwrap();
+		assert_eq!(daily.todo_time.date(), Local::today());
+		assert_eq!((daily.todo_time.hour(), daily.todo_time.minute()), (7, 15));
+		assert!(daily.daily && daily.repeats());
+		assert!(tasks.iter().filter(|x| x.note.title != "daily").all(|x| !x.daily && !x.repeats()));
+	}
+
+	#[tokio::test]
+	async fn memory_set_label() {
+		let backend = MemoryBackend::new();
+		let id = backend.add_task("task", &[("todoDate", &date(0))], false);
+		backend.set_label(&id, "todoDate", &date(2)).await.unwrap();
+		backend.set_label(&id, "todoTime", "18:00:00").await.unwrap();
+		let task = backend.tasks().await.unwrap().remove(0);
+		assert_eq!(task.note.label("todoDate"), Some(&*date(2)));
+		assert_eq!(task.todo_time.hour(), 18);
+		assert_eq!(task.note.attributes.len(), 2);
+
+		backend.set_label(&id, "doneDate", &date(0)).await.unwrap();
+		assert!(backend.tasks().await.unwrap().is_empty());
+		assert!(backend.set_label("missing", "doneDate", &date(0)).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn memory_events() {
+		let backend = MemoryBackend::new();
+		let start = |days| format!("{}T10:00:00", date(days));
+		backend.add_event("past", &[("startTime", &start(-1))]);
+		let id = backend.add_event("future", &[("startTime", &start(2))]);
+		let events = backend.events().await.unwrap();
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].note.note_id, id);
+	}
+
+	#[test]
+	fn list_items() {
+		assert_eq!(append_list_item("", "a"), "<ul><li>a</li></ul>");
+		assert_eq!(append_list_item("<p>x</p><ul><li>a</li></ul>\n", "b"), "<p>x</p><ul><li>a</li><li>b</li></ul>");
+		assert_eq!(append_list_item("<p>x</p>", "b"), "<p>x</p><ul><li>b</li></ul>");
+	}
+}
@@ -0,0 +1,157 @@
+//! Data of inline keyboard buttons, sent back by Telegram when a button is pressed.
+//!
+//! Encoded as `<action>:<argument>`, Telegram allows at most 64 bytes.
+
+use chrono::Duration;
+use telegram_bot::MessageId;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::messenger::Button;
+use crate::{error, parse_duration, Error};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Callback {
+	/// Move the reminder draft of the keyboard message later.
+	ExtendDraft(MessageId, Duration),
+	/// Create the reminder of the draft.
+	SaveDraft(MessageId),
+	/// Skip the next occurrence of a recurring reminder.
+	Skip(String),
+	/// Mark the task done.
+	Done(String),
+	/// Mark the task canceled.
+	Cancel(String),
+	/// Move the task later.
+	Snooze(String, Snooze),
+	/// Move the task to this many days after today, keeping its time.
+	Reschedule(String, i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Snooze {
+	For(Duration),
+	/// Tomorrow morning.
+	Tomorrow,
+}
+
+impl Callback {
+	pub fn button<S: Into<String>>(&self, text: S) -> Button {
+		Button::callback(text, self.to_string())
+	}
+}
+
+impl fmt::Display for Callback {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Callback::ExtendDraft(message_id, by) => write!(f, "{}_cb:{}", short_duration(*by), message_id),
+			Callback::SaveDraft(message_id) => write!(f, "save_cb:{}", message_id),
+			Callback::Skip(note_id) => write!(f, "skip_cb:{}", note_id),
+			Callback::Done(note_id) => write!(f, "done:{}", note_id),
+			Callback::Cancel(note_id) => write!(f, "cancel:{}", note_id),
+			Callback::Snooze(note_id, Snooze::For(by)) => write!(f, "snooze_{}:{}", short_duration(*by), note_id),
+			Callback::Snooze(note_id, Snooze::Tomorrow) => write!(f, "snooze_tomorrow:{}", note_id),
+			Callback::Reschedule(note_id, days) => write!(f, "reschedule_{}d:{}", days, note_id),
+		}
+	}
+}
+
+impl FromStr for Callback {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		let (action, argument) = s.split_once(':').ok_or_else(|| error(format!("invalid callback data {:?}", s)))?;
+		let note_id = argument.to_owned();
+		Ok(match action {
+			"save_cb" => Callback::SaveDraft(MessageId::new(argument.parse()?)),
+			"skip_cb" => Callback::Skip(note_id),
+			"done" => Callback::Done(note_id),
+			"cancel" => Callback::Cancel(note_id),
+			"snooze_tomorrow" => Callback::Snooze(note_id, Snooze::Tomorrow),
+			_ => if let Some(days) = action.strip_prefix("reschedule_").and_then(|x| x.strip_suffix('d')) {
+				Callback::Reschedule(note_id, days.parse()?)
+			} else if let Some(by) = action.strip_prefix("snooze_") {
+				Callback::Snooze(note_id, Snooze::For(parse_duration(by)?))
+			} else if let Some(by) = action.strip_suffix("_cb") {
+				Callback::ExtendDraft(MessageId::new(argument.parse()?), parse_duration(by)?)
+			} else {
+				return Err(error(format!("unknown callback action {:?}", action)));
+			},
+		})
+	}
+}
+
+/// Format a duration in the largest unit that divides it, e.g. `1h` or `90m`.
+fn short_duration(duration: Duration) -> String {
+	let minutes = duration.num_minutes();
+	if minutes % (7 * 24 * 60) == 0 {
+		format!("{}w", minutes / (7 * 24 * 60))
+	} else if minutes % (24 * 60) == 0 {
+		format!("{}d", minutes / (24 * 60))
+	} else if minutes % 60 == 0 {
+		format!("{}h", minutes / 60)
+	} else {
+		format!("{}m", minutes)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip(callback: Callback, data: &str) {
+		assert_eq!(callback.to_string(), data);
+		assert_eq!(data.parse::<Callback>().unwrap(), callback);
+	}
+
+	#[test]
+	fn encoding() {
+		let id = || "abc".to_owned();
+		round_trip(Callback::Done(id()), "done:abc");
+		round_trip(Callback::Cancel(id()), "cancel:abc");
+		round_trip(Callback::Snooze(id(), Snooze::For(Duration::minutes(10))), "snooze_10m:abc");
+		round_trip(Callback::Snooze(id(), Snooze::For(Duration::hours(1))), "snooze_1h:abc");
+		round_trip(Callback::Snooze(id(), Snooze::Tomorrow), "snooze_tomorrow:abc");
+		round_trip(Callback::Reschedule(id(), 1), "reschedule_1d:abc");
+		round_trip(Callback::Reschedule(id(), 0), "reschedule_0d:abc");
+		round_trip(Callback::SaveDraft(MessageId::new(42)), "save_cb:42");
+		round_trip(Callback::Skip(id()), "skip_cb:abc");
+		round_trip(Callback::ExtendDraft(MessageId::new(42), Duration::minutes(10)), "10m_cb:42");
+		round_trip(Callback::ExtendDraft(MessageId::new(42), Duration::days(1)), "1d_cb:42");
+	}
+
+	#[test]
+	fn invalid() {
+		for data in &["done", "unknown:abc", "save_cb:abc", "10m_cb:abc", "snooze_soon:abc", "reschedule_xd:abc", "x_cb:42"] {
+			assert!(data.parse::<Callback>().is_err(), "{}", data);
+		}
+	}
+
+	#[test]
+	fn short_durations() {
+		assert_eq!(short_duration(Duration::minutes(10)), "10m");
+		assert_eq!(short_duration(Duration::minutes(90)), "90m");
+		assert_eq!(short_duration(Duration::hours(1)), "1h");
+		assert_eq!(short_duration(Duration::hours(36)), "36h");
+		assert_eq!(short_duration(Duration::days(2)), "2d");
+		assert_eq!(short_duration(Duration::weeks(2)), "2w");
+	}
+
+	#[test]
+	fn max_length() {
+		// Trilium note IDs have 12 characters
+		let id = "AbCdEfGhIjKl".to_owned();
+		let callbacks = [
+			Callback::Snooze(id.clone(), Snooze::Tomorrow),
+			Callback::Snooze(id.clone(), Snooze::For(Duration::minutes(90))),
+			Callback::Reschedule(id.clone(), 7),
+			Callback::Cancel(id.clone()),
+			Callback::Skip(id),
+			Callback::ExtendDraft(MessageId::new(i64::MAX), Duration::minutes(90)),
+		];
+		for callback in &callbacks {
+			assert!(callback.to_string().len() <= 64, "{}", callback);
+		}
+	}
+}
@@ -0,0 +1,379 @@
+//! Bot commands: parsing of their arguments, `/help` and registration with Telegram.
+
+use chrono::{DateTime, Local, NaiveTime};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::backend::NewTask;
+use crate::recurrence::{self, Recurrence};
+use crate::{error, parse_time_prefix, TimeParser, CLIENT};
+
+/// Largest number of weeks shown by `/week`.
+const MAX_AGENDA_WEEKS: u32 = 8;
+
+/// A command with parsed arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+	Help,
+	Next,
+	Today,
+	Tomorrow,
+	Week { weeks: u32 },
+	Overdue,
+	/// Without arguments the reminder starts now with a placeholder text.
+	RemindMe { time: Option<DateTime<Local>>, text: Option<String> },
+	Every { recurrence: Recurrence, text: String },
+	Todo(NewTask),
+	/// An empty query matches all recurring reminders.
+	Skip { query: String },
+	/// Save the text after the command as a new note, whatever the capture mode.
+	NewNote,
+	Done { query: String },
+	Cancel { query: String },
+}
+
+#[derive(Debug)]
+pub struct CommandSpec {
+	/// Name without the slash.
+	pub name: &'static str,
+	/// Arguments for the usage string, e.g. `<when> <text>`.
+	pub args: &'static str,
+	pub description: &'static str,
+	/// Parse the arguments, `None` if they are invalid.
+	parse: fn(&str, DateTime<Local>) -> Option<Command>,
+}
+
+impl CommandSpec {
+	pub fn usage(&self) -> String {
+		format!("/{} {}", self.name, self.args).trim_end().to_owned()
+	}
+}
+
+/// All commands, in the order they are listed by `/help`.
+pub static COMMANDS: &[CommandSpec] = &[
+	CommandSpec {
+		name: "next",
+		args: "",
+		description: "show the next 10 tasks and events",
+		parse: |args, _| args.is_empty().then_some(Command::Next),
+	},
+	CommandSpec {
+		name: "today",
+		args: "",
+		description: "show today's agenda",
+		parse: |args, _| args.is_empty().then_some(Command::Today),
+	},
+	CommandSpec {
+		name: "tomorrow",
+		args: "",
+		description: "show tomorrow's agenda",
+		parse: |args, _| args.is_empty().then_some(Command::Tomorrow),
+	},
+	CommandSpec {
+		name: "week",
+		args: "[n]",
+		description: "show the agenda of the next n weeks (default 1)",
+		parse: parse_week,
+	},
+	CommandSpec {
+		name: "overdue",
+		args: "",
+		description: "list overdue tasks, oldest first",
+		parse: |args, _| args.is_empty().then_some(Command::Overdue),
+	},
+	CommandSpec {
+		name: "remindme",
+		args: "[<when> <text>]",
+		description: "create a reminder in the future, e.g. /remindme tomorrow 9:00 dentist",
+		parse: parse_remindme,
+	},
+	CommandSpec {
+		name: "todo",
+		args: "<title> [@date] [@time] [#tag ...] [!location]",
+		description: "create a task, e.g. /todo buy milk @tomorrow @18:00 #shopping !supermarket",
+		parse: parse_todo,
+	},
+	CommandSpec {
+		name: "every",
+		args: "<schedule> [time] <text> [until <date>]",
+		description: "create a recurring reminder, e.g. /every weekday 08:00 standup",
+		parse: parse_every,
+	},
+	CommandSpec {
+		name: "skip",
+		args: "[title]",
+		description: "skip the next occurrence of a recurring reminder",
+		parse: |args, _| Some(Command::Skip { query: args.to_owned() }),
+	},
+	CommandSpec {
+		name: "newnote",
+		args: "<text>",
+		description: "save the text as a new note, even in journal mode",
+		parse: |args, _| Some(Command::NewNote).filter(|_| !args.is_empty()),
+	},
+	CommandSpec {
+		name: "done",
+		args: "<title>",
+		description: "mark a task as done",
+		parse: |args, _| Some(Command::Done { query: args.to_owned() }).filter(|_| !args.is_empty()),
+	},
+	CommandSpec {
+		name: "cancel",
+		args: "<title>",
+		description: "cancel a task",
+		parse: |args, _| Some(Command::Cancel { query: args.to_owned() }).filter(|_| !args.is_empty()),
+	},
+	CommandSpec {
+		name: "help",
+		args: "",
+		description: "list all commands",
+		parse: |args, _| args.is_empty().then_some(Command::Help),
+	},
+];
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+	#[error("usage: {}\n{}", .0.usage(), .0.description)]
+	Usage(&'static CommandSpec),
+}
+
+/// Parse a command message like `/remindme in 2h tea` or `/next@my_bot`.
+///
+/// Returns `None` for messages that are not commands and for commands addressed to a different bot.
+/// Only the names in [`COMMANDS`] are commands, other text starting with `/` (e.g. a file path) is not.
+pub fn parse_command(text: &str, bot_username: Option<&str>, now: DateTime<Local>) -> Option<Result<Command, CommandError>> {
+	let text = text.strip_prefix('/')?;
+	let (name, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+	let name = match name.split_once('@') {
+		Some((name, username)) => {
+			if bot_username.is_some_and(|x| !x.eq_ignore_ascii_case(username)) {
+				return None;
+			}
+			name
+		},
+		None => name,
+	};
+	let name = name.to_lowercase();
+	let spec = COMMANDS.iter().find(|x| x.name == name)?;
+	Some((spec.parse)(args.trim(), now).ok_or(CommandError::Usage(spec)))
+}
+
+/// Text of `/help`.
+pub fn help_text() -> String {
+	let mut text = String::new();
+	for spec in COMMANDS {
+		text += &format!("{}\n    {}\n", spec.usage(), spec.description);
+	}
+	text += "\nOther messages are saved as notes.";
+	text
+}
+
+fn parse_week(args: &str, _now: DateTime<Local>) -> Option<Command> {
+	let weeks = if args.is_empty() { 1 } else { args.parse().ok()? };
+	(1..=MAX_AGENDA_WEEKS).contains(&weeks).then_some(Command::Week { weeks })
+}
+
+fn parse_remindme(args: &str, now: DateTime<Local>) -> Option<Command> {
+	if args.is_empty() {
+		return Some(Command::RemindMe { time: None, text: None });
+	}
+	let (time, text) = parse_time_prefix(args, now)?;
+	// a reminder in the past would never be sent
+	if time <= now {
+		return None;
+	}
+	Some(Command::RemindMe {
+		time: Some(time),
+		text: Some(text.to_owned()).filter(|x| !x.is_empty()),
+	})
+}
+
+/// Words starting with `@` are the date or time, `#` starts a tag and everything after `!` is the location.
+fn parse_todo(args: &str, now: DateTime<Local>) -> Option<Command> {
+	let (args, location) = match args.split_once(" !") {
+		Some((args, location)) => (args, Some(location.trim().to_owned()).filter(|x| !x.is_empty())),
+		None => (args, None),
+	};
+	let today = now.date().naive_local();
+	let mut task = NewTask { location, ..NewTask::default() };
+	let mut title = Vec::new();
+	for word in args.split_whitespace() {
+		if let Some(when) = word.strip_prefix('@') {
+			let mut parser = TimeParser::new(when);
+			if let Some((hour, minute)) = parser.time() {
+				task.time = Some(NaiveTime::from_hms_opt(hour, minute, 0)?);
+			} else {
+				task.date = Some(parser.date(today)?.0);
+			}
+			if !parser.rest(when).is_empty() {
+				return None;
+			}
+		} else if let Some(tag) = word.strip_prefix('#').filter(|x| !x.is_empty()) {
+			task.tags.push(tag.to_owned());
+		} else {
+			title.push(word);
+		}
+	}
+	if title.is_empty() {
+		return None;
+	}
+	task.title = title.join(" ");
+	// a time without date is today, or tomorrow if it already passed
+	if let (None, Some(time)) = (task.date, task.time) {
+		task.date = Some(if today.and_time(time) > now.naive_local() { today } else { today.succ_opt()? });
+	}
+	Some(Command::Todo(task))
+}
+
+fn parse_every(args: &str, now: DateTime<Local>) -> Option<Command> {
+	let (recurrence, text) = recurrence::parse_every(args, now.date().naive_local())?;
+	if text.is_empty() {
+		return None;
+	}
+	Some(Command::Every { recurrence, text: text.to_owned() })
+}
+
+#[derive(Serialize)]
+struct BotCommand {
+	command: &'static str,
+	description: &'static str,
+}
+
+#[derive(Serialize)]
+struct SetMyCommands {
+	commands: Vec<BotCommand>,
+}
+
+#[derive(Deserialize)]
+struct TelegramResponse {
+	ok: bool,
+	description: Option<String>,
+}
+
+/// Register the commands with Telegram, so clients suggest them.
+pub async fn register_commands(bot_token: &str) -> Result<(), crate::Error> {
+	let request = SetMyCommands {
+		commands: COMMANDS.iter().map(|x| BotCommand { command: x.name, description: x.description }).collect(),
+	};
+	let response: TelegramResponse = CLIENT.post(format!("https://api.telegram.org/bot{}/setMyCommands", bot_token))
+		.json(&request)
+		.send().await?
+		.json().await?;
+	if !response.ok {
+		return Err(error(format!("setMyCommands failed: {}", response.description.unwrap_or_default())));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::{NaiveDate, TimeZone};
+
+	fn parse(text: &str) -> Option<Command> {
+		parse_command(text, Some("notes_bot"), Local.ymd(2021, 5, 5).and_hms(18, 0, 0))?.ok()
+	}
+
+	#[test]
+	fn remindme() {
+		assert_eq!(parse("/remindme"), Some(Command::RemindMe { time: None, text: None }));
+		assert_eq!(parse("/remindme@notes_bot tomorrow 8:00 dentist"), Some(Command::RemindMe {
+			time: Some(Local.ymd(2021, 5, 6).and_hms(8, 0, 0)),
+			text: Some("dentist".to_owned()),
+		}));
+		assert_eq!(parse("/remindme in 2h"), Some(Command::RemindMe {
+			time: Some(Local.ymd(2021, 5, 5).and_hms(20, 0, 0)),
+			text: None,
+		}));
+		assert_eq!(parse("/remindme@other_bot in 2h"), None);
+	}
+
+	#[test]
+	fn remindme_past() {
+		for text in ["/remindme today 8:00 dentist", "/remindme 2020-01-01 dentist", "/remindme in 0m dentist"] {
+			assert!(matches!(
+				parse_command(text, None, Local.ymd(2021, 5, 5).and_hms(18, 0, 0)),
+				Some(Err(CommandError::Usage(CommandSpec { name: "remindme", .. })))
+			), "{:?}", text);
+		}
+	}
+
+	fn todo(title: &str, date: Option<(i32, u32, u32)>, time: Option<(u32, u32)>, tags: &[&str], location: Option<&str>) -> Option<Command> {
+		Some(Command::Todo(NewTask {
+			title: title.to_owned(),
+			date: date.map(|(y, m, d)| NaiveDate::from_ymd(y, m, d)),
+			time: time.map(|(h, m)| NaiveTime::from_hms(h, m, 0)),
+			tags: tags.iter().map(|x| x.to_string()).collect(),
+			location: location.map(ToOwned::to_owned),
+		}))
+	}
+
+	#[test]
+	fn todo_command() {
+		assert_eq!(parse("/todo buy milk"), todo("buy milk", None, None, &[], None));
+		assert_eq!(parse("/todo buy milk @tomorrow @18:30"), todo("buy milk", Some((2021, 5, 6)), Some((18, 30)), &[], None));
+		assert_eq!(parse("/todo @2021-05-10 file taxes"), todo("file taxes", Some((2021, 5, 10)), None, &[], None));
+		assert_eq!(parse("/todo call #family mom #phone"), todo("call mom", None, None, &["family", "phone"], None));
+		assert_eq!(parse("/todo buy milk #shopping !big supermarket"), todo("buy milk", None, None, &["shopping"], Some("big supermarket")));
+		assert_eq!(parse("/todo buy milk !"), todo("buy milk", None, None, &[], None));
+	}
+
+	#[test]
+	fn todo_time_without_date() {
+		// it is 18:00, a later time is today, an earlier one tomorrow
+		assert_eq!(parse("/todo tea @20:00"), todo("tea", Some((2021, 5, 5)), Some((20, 0)), &[], None));
+		assert_eq!(parse("/todo tea @8:00"), todo("tea", Some((2021, 5, 6)), Some((8, 0)), &[], None));
+		assert_eq!(parse("/todo tea @18:00"), todo("tea", Some((2021, 5, 6)), Some((18, 0)), &[], None));
+	}
+
+	#[test]
+	fn todo_invalid() {
+		for text in ["/todo", "/todo @tomorrow #shopping", "/todo #shopping !supermarket", "/todo tea @2021-13-01", "/todo tea @soon", "/todo tea @25:00", "/todo tea @tomorrow18:00"] {
+			assert!(matches!(
+				parse_command(text, None, Local.ymd(2021, 5, 5).and_hms(18, 0, 0)),
+				Some(Err(CommandError::Usage(CommandSpec { name: "todo", .. })))
+			), "{:?}", text);
+		}
+	}
+
+	fn usage_error(text: &str) -> Option<&'static str> {
+		match parse_command(text, Some("notes_bot"), Local.ymd(2021, 5, 5).and_hms(18, 0, 0))? {
+			Err(CommandError::Usage(spec)) => Some(spec.name),
+			Ok(_) => None,
+		}
+	}
+
+	#[test]
+	fn commands() {
+		assert_eq!(parse("/next"), Some(Command::Next));
+		assert_eq!(parse("/Next@Notes_Bot"), Some(Command::Next));
+		assert_eq!(parse("/week 2"), Some(Command::Week { weeks: 2 }));
+		assert_eq!(parse("/done  buy milk "), Some(Command::Done { query: "buy milk".to_owned() }));
+		assert_eq!(parse("/newnote some text"), Some(Command::NewNote));
+		assert_eq!(parse("/skip"), Some(Command::Skip { query: String::new() }));
+		assert_eq!(usage_error("/done"), Some("done"));
+		assert_eq!(usage_error("/cancel "), Some("cancel"));
+		assert_eq!(usage_error("/newnote"), Some("newnote"));
+		assert_eq!(usage_error("/week 9"), Some("week"));
+		assert_eq!(usage_error("/next now"), Some("next"));
+	}
+
+	#[test]
+	fn not_commands() {
+		let now = Local.ymd(2021, 5, 5).and_hms(18, 0, 0);
+		for text in ["/next@other_bot", "/unknown", "/home/me/notes.txt", "/", "next", " /next"] {
+			assert!(parse_command(text, Some("notes_bot"), now).is_none(), "{:?}", text);
+		}
+	}
+
+	#[test]
+	fn help() {
+		let help = help_text();
+		for spec in COMMANDS {
+			assert!(help.contains(&format!("{}\n    {}\n", spec.usage(), spec.description)), "{}", spec.name);
+		}
+		assert!(help.contains("/todo <title> [@date] [@time] [#tag ...] [!location]\n"));
+		assert!(help.contains("/help\n"));
+	}
+}
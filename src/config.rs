@@ -0,0 +1,386 @@
+//! Configuration file (`config.toml`) with environment variable overrides.
+
+use chrono::{Duration, NaiveTime, Weekday};
+use serde_derive::Deserialize;
+use telegram_bot::UserId;
+use thiserror::Error;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::transcribe::FILE_PLACEHOLDER;
+
+/// Environment variable to change the path of the configuration file.
+pub const CONFIG_PATH_VAR: &str = "TELEGRAM_NOTES_BOT_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+const DEFAULT_DATA_DIR: &str = "data";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+	locale: Option<String>,
+	data_dir: Option<String>,
+	telegram: RawTelegram,
+	trilium: RawTrilium,
+	alerts: RawAlerts,
+	digest: RawDigest,
+	transcription: RawTranscription,
+	forwards: RawForwards,
+	capture: RawCapture,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawTelegram {
+	bot_token: Option<String>,
+	owner: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawTrilium {
+	host: Option<String>,
+	password: Option<String>,
+	token: Option<String>,
+	templates: Templates,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawAlerts {
+	lead_times: Option<Vec<String>>,
+	refresh_interval: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawDigest {
+	morning: Option<String>,
+	evening: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawTranscription {
+	command: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawForwards {
+	group_by_source: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawCapture {
+	mode: Option<String>,
+	journal_prefix: Option<String>,
+}
+
+/// Titles of the template notes imported from the `notes` directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Templates {
+	pub task: String,
+	pub reminder: String,
+	pub daily_reminder: String,
+	pub event: String,
+}
+
+impl Default for Templates {
+	fn default() -> Self {
+		Templates {
+			task: "task template".to_owned(),
+			reminder: "reminder template".to_owned(),
+			daily_reminder: "daily reminder template".to_owned(),
+			event: "event template".to_owned(),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum TriliumAuth {
+	/// Log in with the password to get a token.
+	Password(String),
+	/// ETAPI token created in the Trilium options.
+	Token(String),
+}
+
+/// Where text messages are saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureMode {
+	/// A new note in the inbox.
+	Note,
+	/// A list item in today's day note.
+	Journal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+	De,
+	En,
+}
+
+impl Locale {
+	pub fn weekday_name(self, wd: Weekday) -> &'static str {
+		match self {
+			Locale::De => match wd {
+				Weekday::Mon => "Mo",
+				Weekday::Tue => "Di",
+				Weekday::Wed => "Mi",
+				Weekday::Thu => "Do",
+				Weekday::Fri => "Fr",
+				Weekday::Sat => "Sa",
+				Weekday::Sun => "So",
+			},
+			Locale::En => match wd {
+				Weekday::Mon => "Mo",
+				Weekday::Tue => "Tu",
+				Weekday::Wed => "We",
+				Weekday::Thu => "Th",
+				Weekday::Fri => "Fr",
+				Weekday::Sat => "Sa",
+				Weekday::Sun => "Su",
+			},
+		}
+	}
+}
+
+/// Validated configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+	pub bot_token: String,
+	pub owner: UserId,
+	pub trilium_host: String,
+	pub trilium_auth: TriliumAuth,
+	pub templates: Templates,
+	/// How long before a task or event an alert is sent.
+	pub lead_times: Vec<Duration>,
+	/// How often tasks and events are reloaded to schedule their alerts.
+	pub refresh_interval: std::time::Duration,
+	/// Time of the daily digest with today's agenda and the number of overdue tasks.
+	pub morning_digest: Option<NaiveTime>,
+	/// Time of the daily review of today's open tasks.
+	pub evening_review: Option<NaiveTime>,
+	/// Command that prints the transcript of a voice message, see [`crate::transcribe::CommandTranscriber`].
+	pub transcribe_command: Option<Vec<String>>,
+	/// Whether forwarded messages are saved below a note per channel or user.
+	pub group_forwards: bool,
+	pub capture_mode: CaptureMode,
+	/// Messages starting with this prefix are added to the journal, whatever the capture mode.
+	pub journal_prefix: Option<String>,
+	pub locale: Locale,
+	/// Directory of the state file.
+	pub data_dir: PathBuf,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+	#[error("could not read {0}: {1}")]
+	Io(String, std::io::Error),
+	#[error("could not parse {0}: {1}")]
+	Toml(String, toml::de::Error),
+	#[error("{0} is not set (set it in the config file or via {1})")]
+	Missing(&'static str, &'static str),
+	#[error("invalid value for {0}: {1}")]
+	Invalid(&'static str, String),
+}
+
+impl Config {
+	/// Load the configuration file and apply environment variable overrides.
+	///
+	/// A missing `config.toml` is not an error, all settings can be provided as environment variables.
+	pub fn load() -> Result<Config, Error> {
+		let (path, explicit) = match env::var(CONFIG_PATH_VAR) {
+			Ok(path) => (PathBuf::from(path), true),
+			Err(_) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+		};
+		let raw = if explicit || path.exists() {
+			let text = fs::read_to_string(&path).map_err(|e| Error::Io(path.display().to_string(), e))?;
+			toml::from_str(&text).map_err(|e| Error::Toml(path.display().to_string(), e))?
+		} else {
+			RawConfig::default()
+		};
+		Config::from_raw(raw)
+	}
+
+	/// Parse and validate a configuration file, without environment variable overrides.
+	pub fn from_toml(text: &str) -> Result<Config, Error> {
+		let raw = toml::from_str(text).map_err(|e| Error::Toml("config".to_owned(), e))?;
+		Config::validate(raw)
+	}
+
+	fn from_raw(mut raw: RawConfig) -> Result<Config, Error> {
+		override_env(&mut raw.telegram.bot_token, "TELEGRAM_BOT_TOKEN");
+		override_env(&mut raw.trilium.host, "TRILIUM_HOST");
+		override_env(&mut raw.trilium.password, "TRILIUM_PASSWORD");
+		override_env(&mut raw.trilium.token, "TRILIUM_TOKEN");
+		if let Ok(owner) = env::var("TELEGRAM_USER_ID") {
+			raw.telegram.owner = Some(owner.parse().map_err(|_| Error::Invalid("TELEGRAM_USER_ID", format!("{:?} is not numeric", owner)))?);
+		}
+		Config::validate(raw)
+	}
+
+	fn validate(raw: RawConfig) -> Result<Config, Error> {
+		let bot_token = raw.telegram.bot_token.filter(|x| !x.is_empty())
+			.ok_or(Error::Missing("telegram.bot_token", "TELEGRAM_BOT_TOKEN"))?;
+		let owner = UserId::new(raw.telegram.owner.ok_or(Error::Missing("telegram.owner", "TELEGRAM_USER_ID"))?);
+		let trilium_host = raw.trilium.host.ok_or(Error::Missing("trilium.host", "TRILIUM_HOST"))?;
+		if !trilium_host.starts_with("http://") && !trilium_host.starts_with("https://") {
+			return Err(Error::Invalid("trilium.host", format!("{:?} does not start with http:// or https://", trilium_host)));
+		}
+		let trilium_auth = match (raw.trilium.token, raw.trilium.password) {
+			(Some(token), _) => TriliumAuth::Token(token),
+			(None, Some(password)) => TriliumAuth::Password(password),
+			(None, None) => return Err(Error::Missing("trilium.password", "TRILIUM_PASSWORD")),
+		};
+		let lead_times = raw.alerts.lead_times
+			.unwrap_or_else(|| ["7d", "48h", "24h", "1h", "10m"].iter().map(|x| x.to_string()).collect())
+			.iter()
+			.map(|x| crate::parse_duration(x)
+				.ok().filter(|x| *x > Duration::zero())
+				.ok_or_else(|| Error::Invalid("alerts.lead_times", format!("{:?} is not a positive duration", x))))
+			.collect::<Result<Vec<_>, _>>()?;
+		let refresh_interval = match raw.alerts.refresh_interval {
+			Some(x) => crate::parse_duration(&x)
+				.ok().filter(|x| *x >= Duration::seconds(1))
+				.and_then(|x| x.to_std().ok())
+				.ok_or_else(|| Error::Invalid("alerts.refresh_interval", format!("{:?} is not a duration of at least 1s", x)))?,
+			None => std::time::Duration::from_secs(15 * 60),
+		};
+		let morning_digest = raw.digest.morning.as_deref().map(|x| parse_time_of_day("digest.morning", x)).transpose()?;
+		let evening_review = raw.digest.evening.as_deref().map(|x| parse_time_of_day("digest.evening", x)).transpose()?;
+		let transcribe_command = match raw.transcription.command {
+			Some(command) if command.is_empty() => return Err(Error::Invalid("transcription.command", "the command is empty".to_owned())),
+			Some(command) if !command.iter().any(|x| x.contains(FILE_PLACEHOLDER)) => return Err(Error::Invalid(
+				"transcription.command", format!("none of the arguments contains {}", FILE_PLACEHOLDER)
+			)),
+			command => command,
+		};
+		let capture_mode = match raw.capture.mode.as_deref() {
+			None | Some("note") => CaptureMode::Note,
+			Some("journal") => CaptureMode::Journal,
+			Some(x) => return Err(Error::Invalid("capture.mode", format!("{:?} is not one of \"note\", \"journal\"", x))),
+		};
+		let locale = match raw.locale.as_deref() {
+			None | Some("de") => Locale::De,
+			Some("en") => Locale::En,
+			Some(x) => return Err(Error::Invalid("locale", format!("{:?} is not one of \"de\", \"en\"", x))),
+		};
+		Ok(Config {
+			bot_token,
+			owner,
+			trilium_host,
+			trilium_auth,
+			templates: raw.trilium.templates,
+			lead_times,
+			refresh_interval,
+			morning_digest,
+			evening_review,
+			transcribe_command,
+			group_forwards: raw.forwards.group_by_source,
+			capture_mode,
+			journal_prefix: raw.capture.journal_prefix.filter(|x| !x.is_empty()),
+			locale,
+			data_dir: PathBuf::from(raw.data_dir.unwrap_or_else(|| DEFAULT_DATA_DIR.to_owned())),
+		})
+	}
+}
+
+fn parse_time_of_day(key: &'static str, value: &str) -> Result<NaiveTime, Error> {
+	NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| Error::Invalid(key, format!("{:?} is not a time like \"07:00\"", value)))
+}
+
+fn override_env(value: &mut Option<String>, var: &str) {
+	if let Ok(x) = env::var(var) {
+		*value = Some(x);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MINIMAL: &str = r#"
+		[telegram]
+		bot_token = "123:abc"
+		owner = 1
+		[trilium]
+		host = "http://localhost:8080"
+		token = "token"
+	"#;
+
+	/// Key of the error of the minimal config with `extra` appended.
+	fn error_key(extra: &str) -> &'static str {
+		match Config::from_toml(&format!("{}\n{}", extra, MINIMAL)) {
+			Err(Error::Invalid(key, _)) | Err(Error::Missing(key, _)) => key,
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+
+	#[test]
+	fn defaults() {
+		let config = Config::from_toml(MINIMAL).unwrap();
+		assert_eq!(config.bot_token, "123:abc");
+		assert_eq!(config.owner, UserId::new(1));
+		assert!(matches!(config.trilium_auth, TriliumAuth::Token(ref x) if x == "token"));
+		assert_eq!(config.lead_times.len(), 5);
+		assert_eq!(config.refresh_interval, std::time::Duration::from_secs(15 * 60));
+		assert_eq!(config.capture_mode, CaptureMode::Note);
+		assert_eq!(config.locale, Locale::De);
+		assert_eq!(config.transcribe_command, None);
+		assert_eq!(config.data_dir, PathBuf::from(DEFAULT_DATA_DIR));
+	}
+
+	#[test]
+	fn missing() {
+		for (removed, key) in &[("bot_token", "telegram.bot_token"), ("owner", "telegram.owner"), ("host", "trilium.host"), ("token", "trilium.password")] {
+			let text = MINIMAL.lines().filter(|x| !x.trim_start().starts_with(removed)).collect::<Vec<_>>().join("\n");
+			match Config::from_toml(&text) {
+				Err(Error::Missing(x, _)) => assert_eq!(x, *key),
+				x => panic!("unexpected {:?}", x),
+			}
+		}
+	}
+
+	#[test]
+	fn invalid() {
+		assert_eq!(Config::from_toml(&MINIMAL.replace("http://", "ftp://")).unwrap_err().to_string(),
+			"invalid value for trilium.host: \"ftp://localhost:8080\" does not start with http:// or https://");
+		assert_eq!(error_key("[alerts]\nlead_times = [\"1h\", \"soon\"]"), "alerts.lead_times");
+		assert_eq!(error_key("[alerts]\nlead_times = [\"0m\"]"), "alerts.lead_times");
+		assert_eq!(error_key("[alerts]\nrefresh_interval = \"0s\""), "alerts.refresh_interval");
+		assert_eq!(error_key("[capture]\nmode = \"inbox\""), "capture.mode");
+		assert_eq!(error_key("locale = \"fr\""), "locale");
+		assert_eq!(error_key("[digest]\nmorning = \"7am\""), "digest.morning");
+		assert_eq!(error_key("[transcription]\ncommand = [\"whisper\", \"voice.ogg\"]"), "transcription.command");
+		assert_eq!(error_key("[transcription]\ncommand = []"), "transcription.command");
+	}
+
+	#[test]
+	fn unknown_fields() {
+		assert!(matches!(Config::from_toml(&format!("colour = \"red\"\n{}", MINIMAL)), Err(Error::Toml(..))));
+		assert!(matches!(Config::from_toml(&format!("{}\nsecret = \"x\"", MINIMAL)), Err(Error::Toml(..))));
+	}
+
+	#[test]
+	fn env_overrides() {
+		// the only test that sets these variables
+		env::set_var("TELEGRAM_BOT_TOKEN", "456:def");
+		env::set_var("TELEGRAM_USER_ID", "2");
+		env::set_var("TRILIUM_HOST", "https://notes.example.com");
+		let config = Config::from_raw(toml::from_str(MINIMAL).unwrap());
+		env::set_var("TELEGRAM_USER_ID", "me");
+		let invalid = Config::from_raw(toml::from_str(MINIMAL).unwrap());
+		for var in &["TELEGRAM_BOT_TOKEN", "TELEGRAM_USER_ID", "TRILIUM_HOST"] {
+			env::remove_var(var);
+		}
+		let config = config.unwrap();
+		assert_eq!(config.bot_token, "456:def");
+		assert_eq!(config.owner, UserId::new(2));
+		assert_eq!(config.trilium_host, "https://notes.example.com");
+		assert!(matches!(invalid, Err(Error::Invalid("TELEGRAM_USER_ID", _))));
+	}
+}
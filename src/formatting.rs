@@ -0,0 +1,197 @@
+//! Converting formatted Telegram messages to note HTML.
+
+use telegram_bot::{MessageEntity, MessageEntityKind};
+
+use std::cmp::Reverse;
+
+use crate::escape_html;
+
+/// Text of a message as HTML, with bold, italic, code and links kept.
+///
+/// Telegram gives the entity ranges in UTF-16 code units.
+pub fn to_html(text: &str, entities: &[MessageEntity]) -> String {
+	let units: Vec<u16> = text.encode_utf16().collect();
+	let mut entities: Vec<_> = entities.iter()
+		.filter(|x| x.length > 0)
+		.filter_map(|x| {
+			let start = x.offset as usize;
+			let end = (x.offset + x.length) as usize;
+			let (open, close) = tags(&x.kind, &String::from_utf16_lossy(units.get(start..end)?))?;
+			Some((start, end, open, close))
+		})
+		.collect();
+	entities.sort_by_key(|x| (x.0, Reverse(x.1)));
+	let mut entities = entities.into_iter().peekable();
+
+	let mut html = String::new();
+	// end and closing tag of the entities containing the current position, innermost last
+	let mut open: Vec<(usize, &str)> = Vec::new();
+	let mut pos = 0;
+	for c in text.chars() {
+		while open.last().is_some_and(|x| x.0 <= pos) {
+			html += open.pop().unwrap().1;
+		}
+		while let Some((_, end, tag, close)) = entities.next_if(|x| x.0 <= pos) {
+			// entities may be nested, but must not overlap
+			let end = open.last().map_or(end, |x| end.min(x.0));
+			html += &tag;
+			open.push((end, close));
+		}
+		match c {
+			'\n' if !open.iter().any(|x| x.1 == "</pre>") => html += "<br>",
+			_ => html += &escape_html(c.encode_utf8(&mut [0; 4])),
+		}
+		pos += c.len_utf16();
+	}
+	while let Some((_, close)) = open.pop() {
+		html += close;
+	}
+	html
+}
+
+/// The text from byte `start` on, with the entities moved accordingly.
+pub fn suffix<'a>(text: &'a str, entities: &[MessageEntity], start: usize) -> (&'a str, Vec<MessageEntity>) {
+	let shift = text[..start].encode_utf16().count() as i64;
+	let entities = entities.iter()
+		.filter(|x| x.offset + x.length > shift)
+		.map(|x| MessageEntity {
+			offset: (x.offset - shift).max(0),
+			length: x.length - (shift - x.offset).max(0),
+			kind: x.kind.clone(),
+		})
+		.collect();
+	(&text[start..], entities)
+}
+
+/// The URL if the message consists of nothing but a link.
+pub fn single_url(text: &str, entities: &[MessageEntity]) -> Option<String> {
+	let trimmed = text.trim();
+	let start = text[..text.len() - text.trim_start().len()].encode_utf16().count() as i64;
+	let length = trimmed.encode_utf16().count() as i64;
+	match entities {
+		[entity] if entity.offset == start && entity.length == length => match &entity.kind {
+			MessageEntityKind::Url => Some(link_target(trimmed)),
+			MessageEntityKind::TextLink(url) => Some(url.clone()),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+/// Telegram also detects links without scheme, like `example.com`.
+fn link_target(url: &str) -> String {
+	if url.contains("://") {
+		url.to_owned()
+	} else {
+		format!("http://{}", url)
+	}
+}
+
+/// Opening and closing tag of an entity, `None` if it is not formatted.
+fn tags(kind: &MessageEntityKind, text: &str) -> Option<(String, &'static str)> {
+	let link = |url: &str| Some((format!("<a href=\"{}\">", escape_html(url)), "</a>"));
+	match kind {
+		MessageEntityKind::Bold => Some(("<b>".to_owned(), "</b>")),
+		MessageEntityKind::Italic => Some(("<i>".to_owned(), "</i>")),
+		MessageEntityKind::Code => Some(("<code>".to_owned(), "</code>")),
+		MessageEntityKind::Pre => Some(("<pre>".to_owned(), "</pre>")),
+		MessageEntityKind::TextLink(url) => link(url),
+		MessageEntityKind::Url => link(&link_target(text)),
+		MessageEntityKind::Email => link(&format!("mailto:{}", text)),
+		MessageEntityKind::Mention => link(&format!("https://t.me/{}", text.trim_start_matches('@'))),
+		MessageEntityKind::TextMention(user) => link(&format!("tg://user?id={}", user.id)),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entity(offset: i64, length: i64, kind: MessageEntityKind) -> MessageEntity {
+		MessageEntity { offset, length, kind }
+	}
+
+	#[test]
+	fn plain() {
+		assert_eq!(to_html("a < b & c > \"d\"", &[]), "a &lt; b &amp; c &gt; &quot;d&quot;");
+		assert_eq!(to_html("line\nbreak", &[]), "line<br>break");
+	}
+
+	#[test]
+	fn utf16_offsets() {
+		// the emoji is two UTF-16 code units, ä only one
+		let text = "😀ä bold";
+		assert_eq!(to_html(text, &[entity(4, 4, MessageEntityKind::Bold)]), "😀ä <b>bold</b>");
+		let text = "𝕏 and 😀 code";
+		assert_eq!(to_html(text, &[entity(0, 2, MessageEntityKind::Italic), entity(10, 4, MessageEntityKind::Code)]), "<i>𝕏</i> and 😀 <code>code</code>");
+	}
+
+	#[test]
+	fn nested() {
+		let text = "see docs now";
+		let entities = [
+			entity(4, 8, MessageEntityKind::TextLink("https://example.com/?a=1&b=2".to_owned())),
+			entity(4, 4, MessageEntityKind::Bold),
+		];
+		assert_eq!(to_html(text, &entities), "see <a href=\"https://example.com/?a=1&amp;b=2\"><b>docs</b> now</a>");
+		// overlapping entities are cut at the end of the outer one
+		let entities = [entity(0, 5, MessageEntityKind::Bold), entity(3, 5, MessageEntityKind::Italic)];
+		assert_eq!(to_html("abcdefghij", &entities), "<b>abc<i>de</i></b>fghij");
+	}
+
+	#[test]
+	fn links() {
+		let text = "example.com, a@b.de and @someone";
+		let entities = [
+			entity(0, 11, MessageEntityKind::Url),
+			entity(13, 6, MessageEntityKind::Email),
+			entity(24, 8, MessageEntityKind::Mention),
+			entity(20, 3, MessageEntityKind::Hashtag),
+		];
+		assert_eq!(
+			to_html(text, &entities),
+			"<a href=\"http://example.com\">example.com</a>, <a href=\"mailto:a@b.de\">a@b.de</a> and <a href=\"https://t.me/someone\">@someone</a>"
+		);
+	}
+
+	#[test]
+	fn pre() {
+		let text = "code:\nfn main() {\n\t1 < 2\n}\nend";
+		assert_eq!(
+			to_html(text, &[entity(6, 20, MessageEntityKind::Pre)]),
+			"code:<br><pre>fn main() {\n\t1 &lt; 2\n}</pre><br>end"
+		);
+	}
+
+	#[test]
+	fn invalid_entities() {
+		assert_eq!(to_html("short", &[entity(3, 10, MessageEntityKind::Bold), entity(1, 0, MessageEntityKind::Italic)]), "short");
+	}
+
+	#[test]
+	fn suffix_shifts_entities() {
+		let text = "/newnote 😀 bold _italic_";
+		let entities = [
+			entity(0, 8, MessageEntityKind::BotCommand),
+			entity(12, 4, MessageEntityKind::Bold),
+			entity(17, 8, MessageEntityKind::Italic),
+		];
+		let (rest, entities) = suffix(text, &entities, "/newnote ".len());
+		assert_eq!(rest, "😀 bold _italic_");
+		assert_eq!(entities.iter().map(|x| (x.offset, x.length)).collect::<Vec<_>>(), [(3, 4), (8, 8)]);
+		assert_eq!(to_html(rest, &entities), "😀 <b>bold</b> <i>_italic_</i>");
+
+		// entities starting before the suffix are cut
+		let (rest, entities) = suffix("/newnote bold", &[entity(5, 8, MessageEntityKind::Bold)], 9);
+		assert_eq!(to_html(rest, &entities), "<b>bold</b>");
+	}
+
+	#[test]
+	fn single_link() {
+		assert_eq!(single_url(" example.com ", &[entity(1, 11, MessageEntityKind::Url)]), Some("http://example.com".to_owned()));
+		assert_eq!(single_url("😀", &[entity(0, 2, MessageEntityKind::TextLink("https://a.b".to_owned()))]), Some("https://a.b".to_owned()));
+		assert_eq!(single_url("see example.com", &[entity(4, 11, MessageEntityKind::Url)]), None);
+		assert_eq!(single_url("example.com", &[entity(0, 11, MessageEntityKind::Bold)]), None);
+	}
+}
@@ -29,9 +29,8 @@ pub fn parse_calendar(data: &str) -> Result<Calendar, Error> {
 	let mut name = None;
 	let mut events = Vec::new();
 	for prop in cal.properties {
-		match prop.name.as_ref() {
-			"NAME" => name = Some(prop.value.unwrap_or_default()),
-			_ => {}
+		if prop.name == "NAME" {
+			name = Some(prop.value.unwrap_or_default());
 		}
 	}
 	for event in cal.events {
@@ -64,23 +63,21 @@ fn process_event(event: IcalEvent) -> Result<Event, Error> {
 			"DTEND" => end = Some(process_dt(&value)?),
 			"DURATION" => duration = Some(process_duration(&value)?),
 			"RRULE" => { /* TODO: periodic */ },
-			"X-ALT-DESC" => {
-				if prop.params.as_ref()
-					.map(|x| x.iter()
-						.any(|(key, values)| key == "FMTTYPE" && values.first().map(|x| &**x) == Some("text/html"))
-					).unwrap_or(false) {
-					description_html = Some(value);
-				}
+			"X-ALT-DESC" if prop.params.as_ref()
+				.map(|x| x.iter()
+					.any(|(key, values)| key == "FMTTYPE" && values.first().map(|x| &**x) == Some("text/html"))
+				).unwrap_or(false) => {
+				description_html = Some(value);
 			}
 			_ => (),
 		};
 	}
 	// TODO: don't put defaults here
 	let start = start.ok_or(Error::Data("no dtstart"))?;
-	let end = if end.is_none() && duration.is_some() {
-		start + duration.unwrap()
-	} else {
-		end.ok_or(Error::Data("no dtend"))?
+	let end = match (end, duration) {
+		(Some(end), _) => end,
+		(None, Some(duration)) => start + duration,
+		(None, None) => return Err(Error::Data("no dtend")),
 	};
 	Ok(Event {
 		uid: uid.unwrap_or_default(),
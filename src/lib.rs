@@ -1,52 +1,25 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::Client;
-use serde_json::json;
-use telegram_bot::*;
-use telegram_bot::types::SendMessage;
 use thiserror::Error;
 
-use std::collections::HashMap;
-use std::env;
-use std::sync::Arc;
-use chrono::{DateTime, Datelike, Timelike, Local};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Weekday};
 
+pub mod agenda;
+pub mod backend;
+pub mod callback;
+pub mod command;
+pub mod config;
+pub mod formatting;
 pub mod ical_parsing;
-
-pub static TELEGRAM_BOT_TOKEN: Lazy<String> = Lazy::new(|| {
-	env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN not set")
-});
-
-pub static API: Lazy<Arc<Api>> = Lazy::new(|| {
-	println!("Initializing Telegram API..");
-	Arc::new(Api::new(&*TELEGRAM_BOT_TOKEN))
-});
-
-pub static TRILIUM_HOST: Lazy<String> = Lazy::new(|| {
-	env::var("TRILIUM_HOST").expect("TRILIUM_HOST not set")
-});
-
-pub fn trilium_url(path: &str) -> String {
-	format!("{}{}", *TRILIUM_HOST, path)
-}
-
-pub static TRILIUM_TOKEN: Lazy<String> = Lazy::new(|| {
-	println!("Initializing Trilium API..");
-	let trilium_user = env::var("TRILIUM_USER").expect("TRILIUM_USER not set");
-	let trilium_password = env::var("TRILIUM_PASSWORD").expect("TRILIUM_PASSWORD not set");
-	let client = reqwest::blocking::Client::new();
-	// curl /api/login/token -H 'User-Agent: Mozilla/5.0 ..' -H 'Accept: application/json' -H 'Accept-Language: en' --compressed -H 'Content-Type: application/json' -H 'Origin: moz-extension://13bc3fd7-5cb0-4d48-b368-76e389fd7c5f' -H 'DNT: 1' -H 'Connection: keep-alive' --data '{"username":"username","password":"insert_password_here"}'
-	// -> {"token":"icB3xohFDpkVt7YFpbTflUYC8pucmryVGpb1DFpd6ns="}
-	let resp: HashMap<String, String> = client.post(&trilium_url("/api/login/token"))
-		.json(&json!({ "username": &trilium_user, "password": &trilium_password }))
-		.send().unwrap().json().unwrap();
-	resp["token"].clone()
-});
-
-pub static OWNER: Lazy<UserId> = Lazy::new(|| {
-	println!("Loading configuration..");
-	UserId::new(env::var("TELEGRAM_USER_ID").expect("TELEGRAM_USER_ID not set").parse().expect("TELEGRAM_USER_ID not numeric"))
-});
+pub mod messenger;
+pub mod page;
+pub mod recurrence;
+pub mod scheduler;
+pub mod source;
+pub mod state;
+pub mod transcribe;
+pub mod trilium;
 
 pub static CLIENT: Lazy<Client> = Lazy::new(|| {
 	Client::builder().http1_title_case_headers().build().unwrap()
@@ -57,6 +30,8 @@ pub static CLIENT: Lazy<Client> = Lazy::new(|| {
 pub enum Error {
 	#[error("network error: {0}")]
 	Network(#[from] reqwest::Error),
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
 	#[error("telegram error: {0}")]
 	Telegram(#[from] telegram_bot::Error),
 	#[error("json parsing error: {0}")]
@@ -69,6 +44,10 @@ pub enum Error {
 	Integer(#[from] std::num::ParseIntError),
 	#[error("ical parsing error: {0}")]
 	Ical(#[from] ical_parsing::Error),
+	#[error("trilium error: {0}")]
+	Trilium(#[from] trilium::ApiError),
+	#[error("configuration error: {0}")]
+	Config(#[from] config::Error),
 	#[error("internal error: {0}")]
 	CustomMessage(String),
 }
@@ -77,36 +56,447 @@ pub fn error<S: Into<String>>(msg: S) -> Error {
 	Error::CustomMessage(msg.into())
 }
 
-pub async fn send_message<S: Into<String>>(msg: S) -> Result<(), Error> {
-	API.send(SendMessage::new(*OWNER, msg.into()).parse_mode(ParseMode::Html)).await?;
-	Ok(())
+/// Escape text for messages and notes formatted as HTML.
+pub fn escape_html(s: &str) -> String {
+	s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
 }
 
-pub async fn send_message_markdown<S: Into<String>>(msg: S) -> Result<(), Error> {
-	API.send(SendMessage::new(*OWNER, msg.into()).parse_mode(ParseMode::MarkdownV2)).await?;
-	Ok(())
+/// How well `query` matches `text`, ignoring case: lower is better, `None` if it does not match.
+///
+/// Exact matches come first, then prefixes, substrings, texts containing all words of the query
+/// and finally texts containing the letters of the query in order.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<u32> {
+	let query = query.trim().to_lowercase();
+	let text = text.to_lowercase();
+	if text == query {
+		Some(0)
+	} else if text.starts_with(&query) {
+		Some(1)
+	} else if text.contains(&query) {
+		Some(2)
+	} else if query.split_whitespace().all(|word| text.contains(word)) {
+		Some(3)
+	} else {
+		let mut chars = text.chars();
+		query.chars().filter(|x| !x.is_whitespace()).all(|x| chars.any(|y| y == x)).then_some(4)
+	}
 }
 
-static DATE_TIME_REGEX: Lazy<Regex> = Lazy::new(|| {
-	Regex::new(r"(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})(?:[\sT](?P<hour>\d{2}).(?P<minute>\d{2}))?").unwrap()
+/// Hour used for dates given without a time.
+pub(crate) const DEFAULT_HOUR: u32 = 9;
+
+static WORD_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"\S+").unwrap()
+});
+
+static ISO_DATE_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"^(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})$").unwrap()
 });
 
+/// A date and time written as one word, `2021-05-01T14:00` (lowercase as read by [`TimeParser`]).
+static ISO_DATE_TIME_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"^(\d{4}-\d{2}-\d{2})t(\d{1,2}:\d{2})$").unwrap()
+});
+
+static GERMAN_DATE_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"^(?P<day>\d{1,2})\.(?P<month>\d{1,2})\.(?P<year>\d{4})?$").unwrap()
+});
+
+static TIME_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"^(?P<hour>\d{1,2})(?:[:.](?P<minute>\d{2}))?(?P<suffix>am|pm)?$").unwrap()
+});
+
+static AMOUNT_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"^(?P<amount>\d+)(?P<unit>[a-z]*)$").unwrap()
+});
+
+/// Parse a future point in time like `2021-05-01 14:00`, `2021-05-01T14:00`, `tomorrow 9:00`, `next friday`, `in 2h` or `17:30`.
+///
+/// See [`parse_time_prefix`] for the accepted formats, but dates without time are at 00:00.
 pub fn parse_time<S: AsRef<str>>(s: S) -> Result<DateTime<Local>, Error> {
 	let s = s.as_ref();
-	// YYYY-MM-DD format
-	let data = DATE_TIME_REGEX.captures(s).ok_or_else(|| error("regex failed"))?;
-	let year = data.name("year").unwrap().as_str().parse().unwrap();
-	let month = data.name("month").unwrap().as_str().parse().unwrap();
-	let day = data.name("day").unwrap().as_str().parse().unwrap();
-	let hour = data.name("hour").map(|x| x.as_str().parse().unwrap()).unwrap_or(0);
-	let minute = data.name("minute").map(|x| x.as_str().parse().unwrap()).unwrap_or(0);
-	// TODO: construct this datetime in a more elegant way
-	let dt = Local::now()
-		.with_year(year).unwrap()
-		.with_month(month).unwrap()
-		.with_day(day).unwrap()
-		.with_hour(hour).unwrap()
-		.with_minute(minute).unwrap()
-		.with_second(0).unwrap();
-	Ok(dt)
+	let now = Local::now();
+	let mut parser = TimeParser::new(s);
+	match parser.parse(now, 0) {
+		Some(time) if parser.rest(s).is_empty() => if time > now {
+			Ok(time)
+		} else {
+			Err(error(format!("time {:?} is in the past", s)))
+		},
+		_ => Err(error(format!("could not understand time {:?}", s))),
+	}
+}
+
+/// Parse a point in time at the start of `s` and return it together with the remaining text.
+///
+/// Understands (in English or German):
+/// - offsets: `in 90m`, `in 2d 3h`, `in 2 hours`, `in 10 Minuten`
+/// - dates: `today`, `tomorrow`, `übermorgen`, `friday`, `next friday`, `am Freitag`, `2021-05-01`, `24.12.`, `24.12.2021`
+/// - times: `17:30`, `at 5pm`, `um 9 Uhr`, also after or before a date, or `2021-05-01T14:00`
+///
+/// Dates without time are at 09:00. Times and weekdays without `next` refer to the next
+/// time they happen, e.g. `8:00` in the evening is tomorrow morning.
+/// Explicit dates are returned even if they already passed.
+pub fn parse_time_prefix(s: &str, now: DateTime<Local>) -> Option<(DateTime<Local>, &str)> {
+	let mut parser = TimeParser::new(s);
+	let time = parser.parse(now, DEFAULT_HOUR)?;
+	Some((time, parser.rest(s)))
+}
+
+/// Reads words of a message, see [`parse_time_prefix`].
+pub(crate) struct TimeParser {
+	/// Start offset and lowercase text of the words of the input.
+	words: Vec<(usize, String)>,
+	/// Index of the next word.
+	pub(crate) pos: usize,
+}
+
+impl TimeParser {
+	pub(crate) fn new(s: &str) -> Self {
+		let mut words = Vec::new();
+		for word in WORD_REGEX.find_iter(s) {
+			let text = word.as_str().trim_end_matches(',').to_lowercase();
+			match ISO_DATE_TIME_REGEX.captures(&text) {
+				Some(data) => {
+					words.push((word.start(), data[1].to_owned()));
+					words.push((word.start() + data.get(2).unwrap().start(), data[2].to_owned()));
+				},
+				None => words.push((word.start(), text)),
+			}
+		}
+		TimeParser { words, pos: 0 }
+	}
+
+	/// The part of `s` that was not parsed yet.
+	pub(crate) fn rest<'a>(&self, s: &'a str) -> &'a str {
+		self.words.get(self.pos).map(|x| &s[x.0..]).unwrap_or("")
+	}
+
+	pub(crate) fn next(&mut self) -> Option<String> {
+		let word = self.words.get(self.pos)?.1.clone();
+		self.pos += 1;
+		Some(word)
+	}
+
+	/// Skip the next word if it is one of `options`.
+	pub(crate) fn eat(&mut self, options: &[&str]) -> bool {
+		let found = self.words.get(self.pos).is_some_and(|x| options.contains(&&*x.1));
+		if found {
+			self.pos += 1;
+		}
+		found
+	}
+
+	/// Dates without time are at `default_hour`.
+	fn parse(&mut self, now: DateTime<Local>, default_hour: u32) -> Option<DateTime<Local>> {
+		if self.eat(&["in"]) {
+			return match self.duration() {
+				Some(duration) => Some(now + duration),
+				None => {
+					self.pos = 0;
+					None
+				}
+			};
+		}
+		let today = now.date().naive_local();
+		let mut date = self.date(today);
+		let time = self.time();
+		if date.is_none() && time.is_some() {
+			date = self.date(today);
+		}
+		if date.is_none() && time.is_none() {
+			return None;
+		}
+		// without date: today or tomorrow
+		let (date, skip_days) = date.unwrap_or((today, 1));
+		let (hour, minute) = time.unwrap_or((default_hour, 0));
+		let result = local_time(date, hour, minute)?;
+		if result <= now && skip_days > 0 {
+			local_time(date + chrono::Duration::days(skip_days), hour, minute)
+		} else {
+			Some(result)
+		}
+	}
+
+	/// Parse a date and the number of days to add if the resulting time already passed.
+	pub(crate) fn date(&mut self, today: NaiveDate) -> Option<(NaiveDate, i64)> {
+		let start = self.pos;
+		self.eat(&["on", "am"]);
+		let next = self.eat(&["next", "nächsten", "nächster", "nächste", "naechsten", "kommenden", "kommender"]);
+		let word = self.next().unwrap_or_default();
+		let result = match &*word {
+			"today" | "heute" if !next => Some((today, 0)),
+			"tomorrow" | "morgen" if !next => Some((today + chrono::Duration::days(1), 0)),
+			"übermorgen" | "uebermorgen" if !next => Some((today + chrono::Duration::days(2), 0)),
+			_ => if let Some(weekday) = parse_weekday(&word) {
+				let mut days = (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+				if next && days == 0 {
+					days = 7;
+				}
+				Some((today + chrono::Duration::days(days.into()), if next { 0 } else { 7 }))
+			} else if next {
+				None
+			} else {
+				parse_date(&word, today).map(|x| (x, 0))
+			},
+		};
+		if result.is_none() {
+			self.pos = start;
+		}
+		result
+	}
+
+	/// Parse a time of day as hour and minute.
+	pub(crate) fn time(&mut self) -> Option<(u32, u32)> {
+		let start = self.pos;
+		let at = self.eat(&["at", "um"]);
+		let result = self.next().and_then(|word| {
+			let data = TIME_REGEX.captures(&word)?;
+			let mut hour: u32 = data["hour"].parse().ok()?;
+			let minute: u32 = data.name("minute").map_or(Some(0), |x| x.as_str().parse().ok())?;
+			match data.name("suffix").map(|x| x.as_str()) {
+				Some("pm") if hour < 12 => hour += 12,
+				Some("am") if hour == 12 => hour = 0,
+				None => {
+					// "5 pm"
+					if self.eat(&["pm"]) && hour < 12 {
+						hour += 12;
+					} else if data.name("minute").is_none() && !at {
+						// a bare number is only a time after "at"
+						return None;
+					}
+				},
+				_ => {}
+			}
+			if hour < 24 && minute < 60 {
+				Some((hour, minute))
+			} else {
+				None
+			}
+		});
+		if result.is_some() {
+			self.eat(&["uhr", "o'clock"]);
+		} else {
+			self.pos = start;
+		}
+		result
+	}
+
+	/// Parse durations like `90m`, `2d 3h`, `2h30m` or `2 hours`.
+	fn duration(&mut self) -> Option<chrono::Duration> {
+		let mut total: Option<chrono::Duration> = None;
+		loop {
+			let start = self.pos;
+			let part = self.next().and_then(|word| {
+				let data = AMOUNT_REGEX.captures(&word);
+				match data {
+					Some(data) => {
+						let amount = data["amount"].parse().ok()?;
+						let unit = if data["unit"].is_empty() {
+							self.next()?
+						} else {
+							data["unit"].to_owned()
+						};
+						duration_unit(&unit).map(|x| x * amount)
+					},
+					None => parse_duration(&word).ok(),
+				}
+			});
+			match part {
+				Some(part) => total = Some(total.unwrap_or_else(chrono::Duration::zero) + part),
+				None => {
+					self.pos = start;
+					return total;
+				}
+			}
+		}
+	}
+}
+
+/// The local time, or the first valid local time after it if it was skipped by a DST change.
+pub fn first_local_instant(datetime: NaiveDateTime) -> DateTime<Local> {
+	(0..=24 * 60)
+		.find_map(|minutes| Local.from_local_datetime(&(datetime + chrono::Duration::minutes(minutes))).earliest())
+		.unwrap_or_else(|| Local.from_utc_datetime(&datetime))
+}
+
+pub(crate) fn local_time(date: NaiveDate, hour: u32, minute: u32) -> Option<DateTime<Local>> {
+	Local.from_local_datetime(&date.and_hms_opt(hour, minute, 0)?).earliest()
+}
+
+/// Parse `2021-05-01`, `24.12.2021` or `24.12.` (the next 24th of December).
+pub(crate) fn parse_date(word: &str, today: NaiveDate) -> Option<NaiveDate> {
+	if let Some(data) = ISO_DATE_REGEX.captures(word) {
+		return NaiveDate::from_ymd_opt(data["year"].parse().ok()?, data["month"].parse().ok()?, data["day"].parse().ok()?);
+	}
+	let data = GERMAN_DATE_REGEX.captures(word)?;
+	let month = data["month"].parse().ok()?;
+	let day = data["day"].parse().ok()?;
+	match data.name("year") {
+		Some(year) => NaiveDate::from_ymd_opt(year.as_str().parse().ok()?, month, day),
+		None => {
+			let date = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+			if date < today {
+				NaiveDate::from_ymd_opt(today.year() + 1, month, day)
+			} else {
+				Some(date)
+			}
+		}
+	}
+}
+
+pub(crate) fn parse_weekday(word: &str) -> Option<Weekday> {
+	Some(match word {
+		"monday" | "mon" | "montag" => Weekday::Mon,
+		"tuesday" | "tue" | "dienstag" => Weekday::Tue,
+		"wednesday" | "wed" | "mittwoch" => Weekday::Wed,
+		"thursday" | "thu" | "donnerstag" => Weekday::Thu,
+		"friday" | "fri" | "freitag" => Weekday::Fri,
+		"saturday" | "sat" | "samstag" | "sonnabend" => Weekday::Sat,
+		"sunday" | "sun" | "sonntag" => Weekday::Sun,
+		_ => return None,
+	})
+}
+
+fn duration_unit(unit: &str) -> Option<chrono::Duration> {
+	Some(match unit {
+		"w" | "week" | "weeks" | "woche" | "wochen" => chrono::Duration::weeks(1),
+		"d" | "day" | "days" | "tag" | "tage" | "tagen" => chrono::Duration::days(1),
+		"h" | "hr" | "hrs" | "hour" | "hours" | "std" | "stunde" | "stunden" => chrono::Duration::hours(1),
+		"m" | "min" | "mins" | "minute" | "minutes" | "minuten" => chrono::Duration::minutes(1),
+		"s" | "sec" | "secs" | "second" | "seconds" | "sekunde" | "sekunden" => chrono::Duration::seconds(1),
+		_ => return None,
+	})
+}
+
+static DURATION_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"^(?:\s*\d+\s*[wdhms])+\s*$").unwrap()
+});
+
+static DURATION_PART_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"(?P<amount>\d+)\s*(?P<unit>[wdhms])").unwrap()
+});
+
+/// Parse durations like `10m`, `48h` or `2d 3h`.
+pub fn parse_duration<S: AsRef<str>>(s: S) -> Result<chrono::Duration, Error> {
+	let s = s.as_ref();
+	if !DURATION_REGEX.is_match(s) {
+		return Err(error(format!("invalid duration: {:?}", s)));
+	}
+	let mut duration = chrono::Duration::zero();
+	for part in DURATION_PART_REGEX.captures_iter(s) {
+		let amount = part["amount"].parse()?;
+		duration = duration + match &part["unit"] {
+			"w" => chrono::Duration::weeks(amount),
+			"d" => chrono::Duration::days(amount),
+			"h" => chrono::Duration::hours(amount),
+			"m" => chrono::Duration::minutes(amount),
+			_ => chrono::Duration::seconds(amount),
+		};
+	}
+	Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A Wednesday evening.
+	fn now() -> DateTime<Local> {
+		Local.ymd(2021, 5, 5).and_hms(18, 0, 0)
+	}
+
+	#[test]
+	fn time_prefix() {
+		let cases = [
+			("in 90m tea", "2021-05-05 19:30"),
+			("in 2d 3h tea", "2021-05-07 21:00"),
+			("in 2 hours tea", "2021-05-05 20:00"),
+			("in 10 Minuten tea", "2021-05-05 18:10"),
+			("today 20:00 tea", "2021-05-05 20:00"),
+			("heute um 20 Uhr tea", "2021-05-05 20:00"),
+			("tomorrow tea", "2021-05-06 09:00"),
+			("morgen um 9 Uhr tea", "2021-05-06 09:00"),
+			("übermorgen tea", "2021-05-07 09:00"),
+			("friday tea", "2021-05-07 09:00"),
+			("am Freitag tea", "2021-05-07 09:00"),
+			("next friday tea", "2021-05-07 09:00"),
+			("wednesday 20:00 tea", "2021-05-05 20:00"),
+			("wednesday 8:00 tea", "2021-05-12 08:00"),
+			("next wednesday tea", "2021-05-12 09:00"),
+			("nächsten Mittwoch tea", "2021-05-12 09:00"),
+			("2021-05-01 tea", "2021-05-01 09:00"),
+			("2021-06-01 14:00 tea", "2021-06-01 14:00"),
+			("2021-06-01T14:00 tea", "2021-06-01 14:00"),
+			("24.12. tea", "2021-12-24 09:00"),
+			("1.5. tea", "2022-05-01 09:00"),
+			("24.12.2021 tea", "2021-12-24 09:00"),
+			("19:00 tea", "2021-05-05 19:00"),
+			("17:30 tea", "2021-05-06 17:30"),
+			("at 5pm tea", "2021-05-06 17:00"),
+			("at 5 pm tea", "2021-05-06 17:00"),
+			("um 9 Uhr tea", "2021-05-06 09:00"),
+			("8:00 tomorrow tea", "2021-05-06 08:00"),
+			("tomorrow, 10:00 tea", "2021-05-06 10:00"),
+		];
+		for (input, expected) in cases {
+			let (time, rest) = parse_time_prefix(input, now()).unwrap_or_else(|| panic!("{:?} not parsed", input));
+			assert_eq!((&*time.format("%Y-%m-%d %H:%M").to_string(), rest), (expected, "tea"), "{:?}", input);
+		}
+	}
+
+	#[test]
+	fn time_prefix_only() {
+		let (time, rest) = parse_time_prefix("tomorrow 9:30", now()).unwrap();
+		assert_eq!(time, Local.ymd(2021, 5, 6).and_hms(9, 30, 0));
+		assert_eq!(rest, "");
+	}
+
+	#[test]
+	fn no_time_prefix() {
+		for input in ["tea", "in a while", "5 tea", "next tea", "25:00 tea", "32.12. tea", ""] {
+			assert_eq!(parse_time_prefix(input, now()), None, "{:?}", input);
+		}
+	}
+
+	#[test]
+	fn time() {
+		assert_eq!(parse_time("2999-01-01").unwrap(), Local.ymd(2999, 1, 1).and_hms(0, 0, 0));
+		assert_eq!(parse_time("2999-01-01 14:00").unwrap(), Local.ymd(2999, 1, 1).and_hms(14, 0, 0));
+		assert_eq!(parse_time("2999-01-01T14:00").unwrap(), Local.ymd(2999, 1, 1).and_hms(14, 0, 0));
+		assert!(parse_time("in 1h").unwrap() > Local::now());
+		assert!(parse_time("2020-01-01").unwrap_err().to_string().contains("in the past"));
+		assert!(parse_time("tomorrow tea").is_err());
+	}
+
+	#[test]
+	fn fuzzy() {
+		assert_eq!(fuzzy_match("Pay rent", "pay rent"), Some(0));
+		assert_eq!(fuzzy_match(" pay ", "Pay rent"), Some(1));
+		assert_eq!(fuzzy_match("rent", "Pay rent"), Some(2));
+		assert_eq!(fuzzy_match("rent pay", "Pay rent in May"), Some(3));
+		assert_eq!(fuzzy_match("prnt", "Pay rent"), Some(4));
+		assert_eq!(fuzzy_match("rnp", "Pay rent"), None);
+		assert_eq!(fuzzy_match("tax", "Pay rent"), None);
+	}
+
+	#[test]
+	fn first_instant() {
+		// every midnight of years with DST changes at midnight in some time zones, e.g. America/Sao_Paulo
+		for year in [2018, 2021] {
+			let mut date = NaiveDate::from_ymd(year, 1, 1);
+			while date.year() == year {
+				let midnight = date.and_hms(0, 0, 0);
+				let instant = first_local_instant(midnight);
+				match Local.from_local_datetime(&midnight).earliest() {
+					Some(x) => assert_eq!(instant, x),
+					None => {
+						assert_eq!(instant.date().naive_local(), date);
+						assert!(Local.from_local_datetime(&(instant.naive_local() - chrono::Duration::minutes(1))).earliest().is_none());
+					}
+				}
+				date = date.succ();
+			}
+		}
+	}
 }
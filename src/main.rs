@@ -1,136 +1,373 @@
 use chrono::prelude::*;
-use futures_util::stream::StreamExt;
 use log::debug;
 use mime::Mime;
-use once_cell::sync::Lazy;
-use reqwest::Client;
-use serde_derive::Deserialize;
-use serde_json::json;
-use telegram_bot::{MessageId, types::{EditMessageText, InlineKeyboardButton, InlineKeyboardMarkup, SendMessage}, Update, UpdateKind, MessageKind, CanReplySendMessage, GetFile};
-use telegram_bot::types::refs::ToMessageId;
+use telegram_bot::{Api, CallbackQuery, GetMe, Update, UpdateKind, Message, MessageId, MessageKind, MessageOrChannelPost, GetFile, GetUpdates};
+use telegram_bot::types::refs::{ToFileRef, ToMessageId};
+use tokio::sync::Notify;
 use tokio::task;
 
-use std::time::Duration;
+use std::collections::HashMap;
+use std::future::Future;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use telegram_notes_bot::*;
+use telegram_notes_bot::callback::{Callback, Snooze};
+use telegram_notes_bot::backend::{Attachment, Event, NotesBackend, Task, TriliumBackend};
+use telegram_notes_bot::config::{CaptureMode, Config, TriliumAuth};
+use telegram_notes_bot::command::{help_text, parse_command, register_commands, Command};
+use telegram_notes_bot::{agenda, formatting, page};
+use telegram_notes_bot::page::PageInfo;
+use telegram_notes_bot::messenger::{split_message, Format, Keyboard, Messenger, TelegramMessenger};
+use telegram_notes_bot::scheduler::{note_lead_times, Alert, AlertKind, AlertQueue};
+use telegram_notes_bot::source::Source;
+use telegram_notes_bot::state::{ReminderDraft, SentAlert, State, StateStore};
+use telegram_notes_bot::transcribe::{CommandTranscriber, Transcriber};
+use telegram_notes_bot::trilium::Trilium;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-	task::spawn_blocking(|| {
-		env_logger::init();
-		Lazy::force(&OWNER);
-		Lazy::force(&API);
-		Lazy::force(&TRILIUM_TOKEN);
-	}).await.unwrap();
+	env_logger::init();
+	println!("Loading configuration..");
+	let config = match Config::load() {
+		Ok(config) => config,
+		Err(e) => {
+			eprintln!("Error: {}", e);
+			process::exit(1);
+		}
+	};
+	let state = match StateStore::open(&config.data_dir) {
+		Ok(state) => state,
+		Err(e) => {
+			eprintln!("Error: could not load state from {}: {}", config.data_dir.display(), e);
+			process::exit(1);
+		}
+	};
+	println!("Initializing Telegram API..");
+	let api = Arc::new(Api::new(&config.bot_token));
+	println!("Initializing Trilium API..");
+	let trilium = match &config.trilium_auth {
+		TriliumAuth::Token(token) => Trilium::new(CLIENT.clone(), &config.trilium_host, token),
+		TriliumAuth::Password(password) => Trilium::login(CLIENT.clone(), &config.trilium_host, password).await?,
+	};
+	let username = match api.send(GetMe::new()).await {
+		Ok(me) => me.username,
+		Err(e) => {
+			println!("Error getting bot user: {}", e);
+			None
+		}
+	};
+	if let Err(e) = register_commands(&config.bot_token).await {
+		println!("Error registering commands: {}", e);
+	}
 	println!("Init done!");
 
-	task::spawn(task_alerts());
-	task::spawn(event_alerts());
+	let bot = Arc::new(Bot {
+		backend: Arc::new(TriliumBackend::new(trilium, config.templates.clone(), config.group_forwards)),
+		messenger: Arc::new(TelegramMessenger::new(Arc::clone(&api), config.owner)),
+		transcriber: config.transcribe_command.clone()
+			.map(|command| Box::new(CommandTranscriber::new(command, config.data_dir.join("tmp"))) as Box<dyn Transcriber>),
+		api,
+		username,
+		config,
+		state,
+		alerts_changed: Notify::new(),
+		media_groups: Mutex::new(HashMap::new()),
+	});
 
-	let mut context = Context::default();
+	task::spawn(alerts(Arc::clone(&bot)));
+	if let Some(time) = bot.config.morning_digest {
+		task::spawn(daily(Arc::clone(&bot), time, Daily::Digest));
+	}
+	if let Some(time) = bot.config.evening_review {
+		task::spawn(daily(Arc::clone(&bot), time, Daily::Review));
+	}
 
-	let mut stream = API.stream();
-	while let Some(update) = stream.next().await {
-		if update.is_err() {
-			println!("Telegram error: {:?}", update.err().unwrap());
-			continue;
+	loop {
+		let offset = bot.state.read(|state| state.update_offset);
+		let updates = match bot.api.send(GetUpdates::new().offset(offset).timeout(60)).await {
+			Ok(updates) => updates,
+			Err(e) => {
+				println!("Telegram error: {:?}", e);
+				tokio::time::sleep(Duration::from_secs(5)).await;
+				continue;
+			}
+		};
+		for update in updates {
+			// saved before processing, so an update that crashes the bot is not processed again
+			if let Err(e) = bot.state.update(|state| state.update_offset = update.id + 1) {
+				println!("Error saving state: {}", e);
+			}
+			if let Err(e) = process_one(update, &bot).await {
+				println!("Error: {}", e);
+			}
 		}
+	}
+}
+
+/// Albums arrive as one message per photo or video, the album is saved once no part arrived
+/// or finished downloading for this long.
+const MEDIA_GROUP_DELAY: Duration = Duration::from_secs(3);
+
+/// Part of an album, kept until all parts arrived.
+struct MediaGroupItem {
+	message_id: MessageId,
+	caption: Option<String>,
+	attachment: Attachment,
+}
 
-		if let Err(e) = process_one(update.unwrap(), &mut context).await {
-			println!("Error: {}", e);
+/// Parts of an album received so far.
+struct MediaGroup {
+	items: Vec<MediaGroupItem>,
+	/// Parts that are still downloading.
+	downloading: usize,
+	last_part: Instant,
+	/// Source of forwarded albums.
+	source: Option<Source>,
+}
+
+/// Download a part of an album and buffer it, the album is saved once no more parts arrive.
+///
+/// The album is kept open while the part downloads, so slow downloads (e.g. videos) are not left out.
+/// Documents sent as album cannot be grouped, telegram-bot does not provide their `media_group_id`.
+async fn add_to_media_group(
+	bot: &Arc<Bot>,
+	group: &str,
+	message_id: MessageId,
+	caption: Option<String>,
+	source: Option<Source>,
+	attachment: impl Future<Output = Result<Attachment, Error>>,
+) -> Result<(), Error> {
+	{
+		let mut media_groups = bot.media_groups.lock().unwrap();
+		match media_groups.get_mut(group) {
+			Some(media_group) => {
+				media_group.downloading += 1;
+				media_group.last_part = Instant::now();
+			},
+			None => {
+				media_groups.insert(group.to_owned(), MediaGroup { items: Vec::new(), downloading: 1, last_part: Instant::now(), source });
+				task::spawn(save_media_group(Arc::clone(bot), group.to_owned()));
+			}
 		}
 	}
+	let attachment = attachment.await;
+	let mut media_groups = bot.media_groups.lock().unwrap();
+	// the group is only saved once all downloads are finished
+	let media_group = media_groups.get_mut(group).ok_or_else(|| error("album saved before all parts were downloaded"))?;
+	media_group.downloading -= 1;
+	media_group.last_part = Instant::now();
+	media_group.items.push(MediaGroupItem { message_id, caption, attachment: attachment? });
 	Ok(())
 }
 
-struct Context {
-	reminder_msg: MessageId,
-	reminder_text: String,
-	reminder_start: DateTime<Local>,
-	reminder_time: chrono::Duration,
+async fn save_media_group(bot: Arc<Bot>, group: String) {
+	let MediaGroup { mut items, source, .. } = loop {
+		let wait = match bot.media_groups.lock().unwrap().get(&group) {
+			Some(x) if x.downloading > 0 => MEDIA_GROUP_DELAY,
+			Some(x) => (x.last_part + MEDIA_GROUP_DELAY).saturating_duration_since(Instant::now()),
+			None => return,
+		};
+		if wait.is_zero() {
+			match bot.media_groups.lock().unwrap().remove(&group) {
+				Some(x) => break x,
+				None => return,
+			}
+		}
+		tokio::time::sleep(wait).await;
+	};
+	// downloads may finish out of order
+	items.sort_by_key(|x| x.message_id);
+	let first_message = match items.first() {
+		Some(x) => x.message_id,
+		None => return,
+	};
+	let now = Local::now();
+	let title = items.iter().find_map(|x| caption_title(x.caption.as_deref()))
+		.unwrap_or_else(|| format!("album at {:02}:{:02}", now.hour(), now.minute()));
+	let mut content = source.as_ref().map(Source::header_html).unwrap_or_default();
+	let mut attachments = Vec::new();
+	for (i, item) in items.into_iter().enumerate() {
+		let mut attachment = item.attachment;
+		if let Some(caption) = &item.caption {
+			content += &format!("<p>{}</p>", escape_html(caption));
+		}
+		attachment.title = caption_title(item.caption.as_deref()).unwrap_or_else(|| format!("{} {}", attachment.title, i + 1));
+		attachments.push(attachment);
+	}
+	let count = attachments.len();
+	let result = match bot.backend.create_album(&title, &content, attachments, source.as_ref()).await {
+		Ok(()) => bot.messenger.reply(first_message, &format!("Album with {} files saved :-)", count)).await,
+		Err(e) => Err(e),
+	};
+	if let Err(e) = result {
+		println!("Error saving album: {}", e);
+	}
 }
 
-impl Default for Context {
-	fn default() -> Self {
-		Context {
-			reminder_msg: MessageId::new(1),
-			reminder_text: String::new(),
-			reminder_start: Local::now(),
-			reminder_time: chrono::Duration::minutes(0),
-		}
+/// First non-empty line of a caption.
+fn caption_title(caption: Option<&str>) -> Option<String> {
+	caption?.lines().map(str::trim).find(|x| !x.is_empty()).map(ToOwned::to_owned)
+}
+
+/// Save a text note, with the source of forwarded messages.
+async fn save_text_note(bot: &Bot, message: &Message, title: &str, content: String) -> Result<(), Error> {
+	match &message.forward {
+		Some(forward) => {
+			let source = Source::new(forward);
+			let content = source.header_html() + &content;
+			bot.backend.create_forwarded_note(title, &content, &source).await
+		},
+		None => bot.backend.create_note(title, &content).await,
 	}
 }
 
-async fn process_one(update: Update, context: &mut Context) -> Result<(), Error> {
-	let reminder_msg = &mut context.reminder_msg;
-	let reminder_text = &mut context.reminder_text;
-	let reminder_start = &mut context.reminder_start;
-	let reminder_time = &mut context.reminder_time;
+/// Download a file sent to the bot.
+async fn download(bot: &Bot, file: impl ToFileRef) -> Result<Vec<u8>, Error> {
+	let file = bot.api.send(GetFile::new(file)).await?;
+	let url = file.get_url(&bot.config.bot_token).ok_or_else(|| error("url is none"))?;
+	Ok(CLIENT.get(&url).send().await?.bytes().await?.to_vec())
+}
+
+/// Save a voice message or audio file, with its transcript if transcription is enabled.
+///
+/// Transcription can take minutes, so the file is saved in the background and the message answered once it is done.
+async fn save_audio(bot: &Arc<Bot>, message_id: MessageId, file: impl ToFileRef, title: &str, mime: &str, source: Option<&Source>) -> Result<(), Error> {
+	let data = download(bot, file).await?;
+	let bot = Arc::clone(bot);
+	let (title, mime, source) = (title.to_owned(), mime.to_owned(), source.cloned());
+	task::spawn(async move {
+		if let Err(e) = save_audio_data(&bot, message_id, data, &title, &mime, source.as_ref()).await {
+			println!("Error saving audio: {}", e);
+		}
+	});
+	Ok(())
+}
+
+async fn save_audio_data(bot: &Bot, message_id: MessageId, data: Vec<u8>, title: &str, mime: &str, source: Option<&Source>) -> Result<(), Error> {
+	let extension = match mime.split_once('/').map(|x| x.1) {
+		Some("mpeg") => "mp3",
+		Some("mp4") => "m4a",
+		Some(subtype) => subtype,
+		None => "bin",
+	};
+	let file_name = format!("{}.{}", title, extension);
+	let (transcript, reply) = match &bot.transcriber {
+		Some(transcriber) => match transcriber.transcribe(&data, extension).await {
+			Ok(text) if !text.is_empty() => (Some(text), "Audio saved and transcribed :-)".to_owned()),
+			Ok(_) => (None, "Audio saved, no speech recognized".to_owned()),
+			Err(e) => (None, format!("Audio saved, transcription failed: {}", e)),
+		},
+		None => (None, "Audio saved :-)".to_owned()),
+	};
+	bot.backend.create_file(title, transcript.as_deref(), &file_name, mime, data, source).await?;
+	bot.messenger.reply(message_id, &reply).await?;
+	Ok(())
+}
+
+struct Bot {
+	config: Config,
+	api: Arc<Api>,
+	/// Username of the bot, commands addressed to other bots are ignored.
+	username: Option<String>,
+	backend: Arc<dyn NotesBackend>,
+	messenger: Arc<dyn Messenger>,
+	/// Transcribes voice messages, if configured.
+	transcriber: Option<Box<dyn Transcriber>>,
+	state: StateStore,
+	/// Notified when the bot creates a task or event, so its alerts are scheduled.
+	alerts_changed: Notify,
+	/// Parts of albums by their `media_group_id`.
+	media_groups: Mutex<HashMap<String, MediaGroup>>,
+}
+
+async fn process_one(update: Update, bot: &Arc<Bot>) -> Result<(), Error> {
+	let backend = &*bot.backend;
+	let messenger = &*bot.messenger;
 
 	if let UpdateKind::Message(message) = update.kind {
 		let now = Local::now();
 		println!("[{}-{:02}-{:02} {:02}:{:02}] Receiving msg {:?}", now.year(), now.month(), now.day(), now.hour(), now.minute(), message);
-		if message.from.id != *OWNER {
+		if message.from.id != bot.config.owner {
 			println!("ignoring, not sent by authorized user");
 			return Ok(());
 		}
+		// where forwarded messages were originally posted
+		let source = message.forward.as_ref().map(Source::new);
 
-		if let MessageKind::Text { ref data, .. } = message.kind {
-			if data == "/next" {
-				command_next().await?;
-				return Ok(());
-			} else if data == "/remindme" {
-				let mut msg = SendMessage::new(*OWNER, "in 0m: new reminder");
-				msg.reply_markup(get_keyboard());
-				*reminder_msg = API.send(msg).await?.to_message_id();
-				*reminder_text = "new reminder".to_owned();
-				*reminder_time = chrono::Duration::minutes(0);
-				*reminder_start = Local::now();
-				return Ok(());
-			} else if !reminder_text.is_empty() {
+		if let MessageKind::Text { ref data, ref entities } = message.kind {
+			let mut mode = bot.config.capture_mode;
+			// start of the text to save, after the command or prefix
+			let mut start = 0;
+			if let Some(command) = parse_command(data, bot.username.as_deref(), now) {
+				match command {
+					Ok(Command::NewNote) => {
+						mode = CaptureMode::Note;
+						let args = data.split_once(char::is_whitespace).map_or("", |x| x.1.trim_start());
+						start = data.len() - args.len();
+					},
+					Ok(command) => {
+						run_command(bot, message.id, command).await?;
+						return Ok(());
+					},
+					Err(e) => {
+						messenger.reply(message.id, &e.to_string()).await?;
+						return Ok(());
+					}
+				}
+			} else if let Some(rest) = bot.config.journal_prefix.as_deref().and_then(|x| data.strip_prefix(x)).filter(|x| !x.trim().is_empty()) {
+				mode = CaptureMode::Journal;
+				start = data.len() - rest.trim_start().len();
+			} else if let Some(mut draft) = message.reply_to_message.as_ref()
+					.and_then(|x| bot.state.read(|state| state.draft(x.to_message_id()).cloned())) {
 				if data.starts_with("time ") && data.len() > 5 {
-					let time = parse_time(&data[5..]);
-					match time {
+					match parse_time(&data[5..]) {
 						Ok(time) => {
-							*reminder_start = time;
-							send_message(format!("got time {}", reminder_start.format("%Y-%m-%d %H:%M"))).await
+							draft.start = time;
+							messenger.send_message(&format!("got time {}", draft.start.format("%Y-%m-%d %H:%M"))).await?;
+							messenger.edit(draft.message_id, &draft_text(bot, &draft), Format::Plain, Some(&get_keyboard(draft.message_id))).await?;
 						},
-						Err(e) => send_message(format!("{:?}", e)).await,
-					}?;
-					return Ok(());
+						Err(e) => {
+							messenger.send_message(&format!("{:?}", e)).await?;
+							return Ok(());
+						},
+					}
 				} else {
-					*reminder_text = data.to_owned();
-					let mut edit = EditMessageText::new(*OWNER, *reminder_msg, format!("in {}: {}", format_time(*reminder_time), reminder_text));
-					edit.reply_markup(get_keyboard());
-					API.send(edit).await?;
-					return Ok(());
+					draft.text = data.to_owned();
+					messenger.edit(draft.message_id, &draft_text(bot, &draft), Format::Plain, Some(&get_keyboard(draft.message_id))).await?;
 				}
+				bot.state.update(|state| state.set_draft(draft))?;
+				return Ok(());
 			}
-			let is_url = false; //Url::parse(&data).is_ok(); // TODO: read this data from the Telegram json data (utf16 idxes..)
-			let formatted_text = if is_url {
-				format!("<ul><li><a href=\"{}\">{}</a></li></ul>", data, data)
-			} else {
-				format!("<ul><li>{}</li></ul>", data)
-			};
-			let title = format!("{} found at {:02}:{:02}", if is_url { "URL" } else { "content" }, now.hour(), now.minute());
-			create_text_note(&CLIENT, &*TRILIUM_TOKEN,
-				&title,
-				&formatted_text
-			).await?;
-
-			// answer message
-			if is_url {
-				API.send(message.text_reply("URL saved :-)")).await?;
-			} else {
-				API.send(message.text_reply("Text saved :-)")).await?;
+			let (data, entities) = formatting::suffix(data, entities, start);
+			// forwarded messages are always saved as notes, to keep their source
+			if mode == CaptureMode::Journal && message.forward.is_none() {
+				backend.append_to_journal(&format!("{:02}:{:02} {}", now.hour(), now.minute(), formatting::to_html(data, &entities))).await?;
+				messenger.reply(message.id, "Added to journal :-)").await?;
+				return Ok(());
 			}
+			if let Some(url) = formatting::single_url(data, &entities) {
+				let page = match page::fetch(&CLIENT, &url).await {
+					Ok(page) => page,
+					Err(e) => {
+						println!("error fetching {}: {}", url, e);
+						PageInfo::default()
+					}
+				};
+				let title = page.title.clone().unwrap_or_else(|| url.clone());
+				save_text_note(bot, &message, &title, page.to_html(&url)).await?;
+				messenger.reply(message.id, &format!("URL saved: {}", title)).await?;
+				return Ok(());
+			}
+			let formatted_text = format!("<ul><li>{}</li></ul>", formatting::to_html(data, &entities));
+			let title = format!("content found at {:02}:{:02}", now.hour(), now.minute());
+			save_text_note(bot, &message, &title, formatted_text).await?;
+			messenger.reply(message.id, "Text saved :-)").await?;
 		} else if let MessageKind::Document { ref data, ref caption, .. } = message.kind {
 			let document = data;
-			let get_file = GetFile::new(&document);
-			let file = API.send(get_file).await?;
-			let url = file.get_url(&TELEGRAM_BOT_TOKEN).ok_or_else(|| error("url is none"))?;
-			let data = CLIENT.get(&url).send().await?.bytes().await?;
-			let mime: Mime = document.mime_type.as_ref().ok_or_else(|| error("no mime type"))?.parse()?;
+			let data = download(bot, document).await?;
+			let mime: Mime = document.mime_type.as_deref().unwrap_or("application/octet-stream").parse()?;
 			match (mime.type_(), mime.subtype()) {
 				(mime::TEXT, x) if x == "calendar" => {
 					let text = String::from_utf8_lossy(&data);
@@ -141,138 +378,491 @@ async fn process_one(update: Update, context: &mut Context) -> Result<(), Error>
 					if calendar.events.len() != 1 {
 						return Ok(());
 					}
-					if CLIENT.get(&trilium_url("/custom/new_event")).form(&json!({
-						"uid": calendar.events[0].uid,
-						"name": calendar.events[0].summary,
-						"summary": calendar.events[0].description.replace("\\n", "\n"),
-						"summaryHtml": calendar.events[0].description_html.as_deref().map(|x| x.replace("\\n", "\n")).unwrap_or_default(),
-						"fileName": document.file_name,
-						"fileData": text,
-						"location": calendar.events[0].location,
-						"startTime": calendar.events[0].start.format("%Y-%m-%dT%H:%M:%S").to_string(),
-						"endTime": calendar.events[0].end.format("%Y-%m-%dT%H:%M:%S").to_string(),
-					})).send().await?.status().is_success() {
-						send_message("Event saved :-)").await?;
-					} else {
-						send_message("error saving event").await?;
+					match backend.create_event(&calendar.events[0], document.file_name.as_deref().unwrap_or("event.ics"), &text).await {
+						Ok(_) => {
+							bot.alerts_changed.notify_one();
+							messenger.send_message("Event saved :-)").await?
+						},
+						Err(e) => messenger.send_message(&format!("error saving event: {}", e)).await?,
 					}
 				},
 				_ => {
-					send_message(format!("Document {:?} {:?} {:?} {:?}", caption, document.file_id, document.file_name, document.mime_type)).await?;
+					let file_name = document.file_name.clone().unwrap_or_else(|| "file".to_owned());
+					let title = caption_title(caption.as_deref()).unwrap_or_else(|| file_name.clone());
+					backend.create_file(&title, None, &file_name, mime.essence_str(), data, source.as_ref()).await?;
+					messenger.reply(message.id, "File saved :-)").await?;
 				}
 			}
+		} else if let MessageKind::Photo { ref data, ref caption, ref media_group_id } = message.kind {
+			// Telegram sends several sizes of the photo
+			let photo = data.iter().max_by_key(|x| x.width * x.height).ok_or_else(|| error("photo without sizes"))?;
+			// photos are always re-encoded as JPEG
+			let attachment = async {
+				Ok(Attachment {
+					title: "photo".to_owned(),
+					file_name: "photo.jpg".to_owned(),
+					mime: "image/jpeg".to_owned(),
+					data: download(bot, photo).await?,
+				})
+			};
+			match media_group_id {
+				Some(group) => add_to_media_group(bot, group, message.id, caption.clone(), source, attachment).await?,
+				None => {
+					let attachment = attachment.await?;
+					let title = caption_title(caption.as_deref()).unwrap_or_else(|| format!("photo at {:02}:{:02}", now.hour(), now.minute()));
+					backend.create_image(&title, &attachment.mime, attachment.data, source.as_ref()).await?;
+					messenger.reply(message.id, "Photo saved :-)").await?;
+				}
+			}
+		} else if let MessageKind::Video { ref data, ref caption, ref media_group_id } = message.kind {
+			let mime = data.mime_type.clone().unwrap_or_else(|| "video/mp4".to_owned());
+			let extension = mime.split_once('/').map_or("mp4", |x| x.1);
+			let attachment = async {
+				Ok(Attachment {
+					title: "video".to_owned(),
+					file_name: format!("video.{}", extension),
+					mime: mime.clone(),
+					data: download(bot, data).await?,
+				})
+			};
+			match media_group_id {
+				Some(group) => add_to_media_group(bot, group, message.id, caption.clone(), source, attachment).await?,
+				None => {
+					let attachment = attachment.await?;
+					let title = caption_title(caption.as_deref()).unwrap_or_else(|| format!("video at {:02}:{:02}", now.hour(), now.minute()));
+					backend.create_file(&title, None, &attachment.file_name, &mime, attachment.data, source.as_ref()).await?;
+					messenger.reply(message.id, "Video saved :-)").await?;
+				}
+			}
+		} else if let MessageKind::Voice { ref data } = message.kind {
+			let title = format!("voice message at {:02}:{:02}", now.hour(), now.minute());
+			let mime = data.mime_type.as_deref().unwrap_or("audio/ogg");
+			save_audio(bot, message.id, data, &title, mime, source.as_ref()).await?;
+		} else if let MessageKind::Audio { ref data } = message.kind {
+			let title = match (&data.performer, &data.title) {
+				(Some(performer), Some(title)) => format!("{} – {}", performer, title),
+				(None, Some(title)) => title.clone(),
+				_ => format!("audio at {:02}:{:02}", now.hour(), now.minute()),
+			};
+			let mime = data.mime_type.as_deref().unwrap_or("audio/mpeg");
+			save_audio(bot, message.id, data, &title, mime, source.as_ref()).await?;
 		}
 	} else if let UpdateKind::CallbackQuery(cb) = update.kind {
-		match &*cb.data.unwrap_or_default() {
-			"10m_cb" => {
-				*reminder_time = reminder_time.checked_add(&chrono::Duration::minutes(10)).unwrap();
-				let mut edit = EditMessageText::new(*OWNER, *reminder_msg, format!("in {}: {}", format_time(*reminder_time), reminder_text));
-				edit.reply_markup(get_keyboard());
-				API.send(edit).await?;
-			},
-			"1h_cb" => {
-				*reminder_time = reminder_time.checked_add(&chrono::Duration::hours(1)).unwrap();
-				let mut edit = EditMessageText::new(*OWNER, *reminder_msg, format!("in {}: {}", format_time(*reminder_time), reminder_text));
-				edit.reply_markup(get_keyboard());
-				API.send(edit).await?;
-			},
-			"1d_cb" => {
-				*reminder_time = reminder_time.checked_add(&chrono::Duration::days(1)).unwrap();
-				let mut edit = EditMessageText::new(*OWNER, *reminder_msg, format!("in {}: {}", format_time(*reminder_time), reminder_text));
-				edit.reply_markup(get_keyboard());
-				API.send(edit).await?;
-			},
-			"1w_cb" => {
-				*reminder_time = reminder_time.checked_add(&chrono::Duration::days(7)).unwrap();
-				let mut edit = EditMessageText::new(*OWNER, *reminder_msg, format!("in {}: {}", format_time(*reminder_time), reminder_text));
-				edit.reply_markup(get_keyboard());
-				API.send(edit).await?;
-			},
-			"save_cb" => {
-				let remind_time = *reminder_start + *reminder_time;
-				CLIENT.get(&trilium_url("/custom/new_reminder")).form(&json!({
-					"time": remind_time.to_rfc3339(),
-					"task": *reminder_text
-				})).send().await?;
-				API.send(SendMessage::new(*OWNER, format!("Reminder scheduled for {} :-)", remind_time.format("%Y-%m-%d %H:%M")))).await?;
-				*reminder_text = String::new();
-			},
-			_ => {}
-		}
+		let answer = match cb.data.as_deref().unwrap_or_default().parse() {
+			Ok(callback) => handle_callback(bot, &cb, callback).await?,
+			Err(_) => Some("Unknown button"),
+		};
+		messenger.answer_callback(&cb, answer).await?;
 	} else {
 		println!("{:?}", update.kind);
 	}
 	Ok(())	
 }
 
-fn get_keyboard() -> InlineKeyboardMarkup {
-	let mut keyboard = InlineKeyboardMarkup::new();
-	let key = InlineKeyboardButton::callback("10m", "10m_cb");
-	let key2 = InlineKeyboardButton::callback("1h", "1h_cb");
-	let key3 = InlineKeyboardButton::callback("1d", "1d_cb");
-	let key4 = InlineKeyboardButton::callback("1w", "1w_cb");
+/// Perform the action of an inline keyboard button and return the notification to show, if any.
+async fn handle_callback(bot: &Bot, cb: &CallbackQuery, callback: Callback) -> Result<Option<&'static str>, Error> {
+	match callback {
+		Callback::ExtendDraft(message_id, by) => match bot.state.read(|state| state.draft(message_id).cloned()) {
+			Some(draft) => extend_draft(bot, draft, by).await?,
+			None => return Ok(Some("This reminder was already saved")),
+		},
+		Callback::SaveDraft(message_id) => match bot.state.read(|state| state.draft(message_id).cloned()) {
+			Some(draft) => save_draft(bot, draft).await?,
+			None => return Ok(Some("This reminder was already saved")),
+		},
+		Callback::Skip(note_id) => {
+			let text = skip_next(bot, &note_id).await?;
+			bot.messenger.send(&text, Format::Plain, None).await?;
+		},
+		Callback::Done(note_id) => return resolve_task(bot, cb, &note_id, Resolution::Done).await,
+		Callback::Cancel(note_id) => return resolve_task(bot, cb, &note_id, Resolution::Cancel).await,
+		Callback::Snooze(note_id, snooze) => {
+			let time = snooze_task(bot, &note_id, snooze).await?;
+			show_task_state(bot, cb, &format!("💤 snoozed until {}", format_date_time(bot, time))).await?;
+		},
+		Callback::Reschedule(note_id, days) => {
+			let date = Local::today().naive_local() + chrono::Duration::days(days);
+			bot.backend.set_label(&note_id, "todoDate", &date.format("%Y-%m-%d").to_string()).await?;
+			bot.alerts_changed.notify_one();
+			show_task_state(bot, cb, &format!("➡️ moved to {} {}", bot.config.locale.weekday_name(date.weekday()), date.format("%Y-%m-%d"))).await?;
+		},
+	}
+	Ok(None)
+}
+
+/// Move a task later and return its new time.
+async fn snooze_task(bot: &Bot, note_id: &str, snooze: Snooze) -> Result<DateTime<Local>, Error> {
+	let time = match snooze {
+		// labels only store whole seconds
+		Snooze::For(duration) => (Local::now() + duration).with_nanosecond(0).unwrap(),
+		Snooze::Tomorrow => parse_time("tomorrow 9:00")?,
+	};
+	let task = bot.backend.tasks().await?.into_iter().find(|x| x.note.note_id == note_id);
+	match task {
+		// the schedule stays unchanged, the occurrence is repeated by a new reminder
+		Some(task) if task.repeats() => bot.backend.create_reminder(time, &task.note.title).await?,
+		task => {
+			bot.backend.set_label(note_id, "todoDate", &time.format("%Y-%m-%d").to_string()).await?;
+			bot.backend.set_label(note_id, "todoTime", &time.format("%H:%M:%S").to_string()).await?;
+			// alerts that would have been sent before the new time are not sent right away
+			if let Some(task) = task.filter(|x| !x.is_reminder) {
+				let now = Local::now();
+				bot.state.update(|state| {
+					for lead in note_lead_times(&task.note, &bot.config.lead_times) {
+						if time - lead <= now {
+							state.sent_alerts.insert(SentAlert { note_id: note_id.to_owned(), due: time, lead_minutes: lead.num_minutes() });
+						}
+					}
+				})?;
+			}
+		}
+	}
+	bot.alerts_changed.notify_one();
+	Ok(time)
+}
+
+/// Mark a task as done or canceled and show its new state.
+async fn resolve_task(bot: &Bot, cb: &CallbackQuery, note_id: &str, resolution: Resolution) -> Result<Option<&'static str>, Error> {
+	let task = bot.backend.tasks().await?.into_iter().find(|x| x.note.note_id == note_id);
+	if task.as_ref().is_some_and(Task::repeats) {
+		return Ok(Some("Repeating reminders can only be snoozed"));
+	}
+	let title = task.map(|x| x.note.title);
+	bot.backend.set_label(note_id, resolution.label(), &resolution.value()).await?;
+	bot.alerts_changed.notify_one();
+	let mut task_state = resolution.state().to_owned();
+	// the keyboard of /done and /cancel only shows the title on its buttons
+	if let (Some(title), Some(MessageOrChannelPost::Message(message))) = (title, &cb.message) {
+		if !matches!(&message.kind, MessageKind::Text { data, .. } if data.contains(&title)) {
+			task_state += &format!(": {}", title);
+		}
+	}
+	show_task_state(bot, cb, &task_state).await?;
+	Ok(None)
+}
+
+/// Replace the keyboard of an alert with the new state of its task.
+async fn show_task_state(bot: &Bot, cb: &CallbackQuery, task_state: &str) -> Result<(), Error> {
+	if let Some(MessageOrChannelPost::Message(message)) = &cb.message {
+		if let MessageKind::Text { data, .. } = &message.kind {
+			bot.messenger.edit(message.id, &format!("{}\n{}", data, task_state), Format::Plain, None).await?;
+		}
+	}
+	Ok(())
+}
+
+async fn run_command(bot: &Bot, message_id: MessageId, command: Command) -> Result<(), Error> {
+	match command {
+		Command::Help => {
+			bot.messenger.send(&help_text(), Format::Plain, None).await?;
+		},
+		Command::Next => command_next(bot).await?,
+		Command::Today => send_agenda(bot, Local::today().naive_local(), 1, false).await?,
+		Command::Tomorrow => send_agenda(bot, Local::today().naive_local() + chrono::Duration::days(1), 1, false).await?,
+		Command::Week { weeks } => send_agenda(bot, Local::today().naive_local(), 7 * i64::from(weeks), true).await?,
+		Command::Overdue => command_overdue(bot).await?,
+		// saved by process_one, which has the formatting of the message
+		Command::NewNote => {},
+		Command::RemindMe { time, text } => {
+			// the keyboard is added once the message ID is known, it is part of the callback data
+			let mut draft = ReminderDraft {
+				message_id: MessageId::new(0),
+				text: text.unwrap_or_else(|| "new reminder".to_owned()),
+				start: time.unwrap_or_else(Local::now),
+				offset_minutes: 0,
+			};
+			draft.message_id = bot.messenger.send(&draft_text(bot, &draft), Format::Plain, None).await?;
+			bot.messenger.edit(draft.message_id, &draft_text(bot, &draft), Format::Plain, Some(&get_keyboard(draft.message_id))).await?;
+			bot.state.update(|state| state.set_draft(draft))?;
+		},
+		Command::Every { recurrence, text } => {
+			bot.backend.create_recurring_reminder(&recurrence, &text).await?;
+			bot.alerts_changed.notify_one();
+			bot.messenger.reply(message_id, &format!("Recurring reminder saved: {}: {}", recurrence.describe(), text)).await?;
+		},
+		Command::Skip { query } => command_skip(bot, &query).await?,
+		Command::Done { query } => command_resolve(bot, message_id, &query, Resolution::Done).await?,
+		Command::Cancel { query } => command_resolve(bot, message_id, &query, Resolution::Cancel).await?,
+		Command::Todo(task) => {
+			bot.backend.create_task(&task).await?;
+			bot.alerts_changed.notify_one();
+			let mut text = format!("Task saved: {}", task.title);
+			match (task.date, task.time) {
+				(Some(date), Some(time)) => text += &format!(", due {} {}", date.format("%Y-%m-%d"), time.format("%H:%M")),
+				(Some(date), None) => text += &format!(", due {}", date.format("%Y-%m-%d")),
+				_ => {}
+			}
+			for tag in &task.tags {
+				text += &format!(" #{}", tag);
+			}
+			if let Some(location) = &task.location {
+				text += &format!(" @ {}", location);
+			}
+			bot.messenger.reply(message_id, &text).await?;
+		},
+	}
+	Ok(())
+}
+
+fn format_date_time(bot: &Bot, time: DateTime<Local>) -> String {
+	format!("{} {}", bot.config.locale.weekday_name(time.weekday()), time.format("%Y-%m-%d %H:%M"))
+}
+
+fn draft_text(bot: &Bot, draft: &ReminderDraft) -> String {
+	let mut text = format_date_time(bot, draft.time());
+	if draft.offset_minutes != 0 {
+		text += &format!(" (+{})", format_time(draft.offset()));
+	}
+	text + ": " + &draft.text
+}
+
+async fn extend_draft(bot: &Bot, mut draft: ReminderDraft, time: chrono::Duration) -> Result<(), Error> {
+	draft.offset_minutes += time.num_minutes();
+	bot.messenger.edit(draft.message_id, &draft_text(bot, &draft), Format::Plain, Some(&get_keyboard(draft.message_id))).await?;
+	bot.state.update(|state| state.set_draft(draft))?;
+	Ok(())
+}
+
+async fn save_draft(bot: &Bot, draft: ReminderDraft) -> Result<(), Error> {
+	let remind_time = draft.time();
+	bot.backend.create_reminder(remind_time, &draft.text).await?;
+	bot.alerts_changed.notify_one();
+	bot.messenger.send(&format!("Reminder scheduled for {} :-)", remind_time.format("%Y-%m-%d %H:%M")), Format::Plain, None).await?;
+	bot.state.update(|state| state.remove_draft(draft.message_id))?;
+	Ok(())
+}
+
+fn get_keyboard(message_id: MessageId) -> Keyboard {
+	let mut keyboard = Keyboard::new();
+	let key = Callback::ExtendDraft(message_id, chrono::Duration::minutes(10)).button("10m");
+	let key2 = Callback::ExtendDraft(message_id, chrono::Duration::hours(1)).button("1h");
+	let key3 = Callback::ExtendDraft(message_id, chrono::Duration::days(1)).button("1d");
+	let key4 = Callback::ExtendDraft(message_id, chrono::Duration::weeks(1)).button("1w");
 	keyboard.add_row(vec![key, key2, key3, key4]);
-	let key = InlineKeyboardButton::callback("save", "save_cb");
+	let key = Callback::SaveDraft(message_id).button("save");
 	keyboard.add_row(vec![key]);
 	keyboard
 }
 
-async fn create_text_note(client: &Client, trilium_token: &str, title: &str, content: &str) -> Result<(), Error> {
-	// creating a note:
-	// curl /api/clipper/notes
-	//  -H 'Accept: */*' -H 'Accept-Language: en' --compressed -H 'Content-Type: application/json'
-	//  -H 'Authorization: icB3xohFDpkVt7YFpbTflUYC8pucmryVGpb1DFpd6ns='
-	//  -H 'trilium-local-now-datetime: 2020-05-29 __:__:__.xxx+__:__'
-	//  -H 'Origin: moz-extension://13bc3fd7-5cb0-4d48-b368-76e389fd7c5f'
-	//  --data '{"title":"line 1","content":"<p>line 2</p><p>line 3</p>","clipType":"note"}'
+/// Buttons of a task or reminder alert.
+fn alert_keyboard(alert: &Alert) -> Keyboard {
+	let note_id = &alert.note_id;
+	let mut keyboard = Keyboard::new();
+	if !alert.recurring {
+		keyboard.add_row(vec![
+			Callback::Done(note_id.clone()).button("✅ Done"),
+			Callback::Cancel(note_id.clone()).button("❌ Cancel"),
+		]);
+	}
+	keyboard.add_row(vec![
+		Callback::Snooze(note_id.clone(), Snooze::For(chrono::Duration::minutes(10))).button("💤 10m"),
+		Callback::Snooze(note_id.clone(), Snooze::For(chrono::Duration::hours(1))).button("💤 1h"),
+		Callback::Snooze(note_id.clone(), Snooze::Tomorrow).button("💤 tomorrow"),
+	]);
+	keyboard
+}
+
+/// How a task is closed by `/done`, `/cancel` or the alert buttons.
+#[derive(Debug, Clone, Copy)]
+enum Resolution {
+	Done,
+	Cancel,
+}
+
+impl Resolution {
+	fn label(self) -> &'static str {
+		match self {
+			Resolution::Done => "doneDate",
+			Resolution::Cancel => "canceled",
+		}
+	}
+
+	fn value(self) -> String {
+		match self {
+			Resolution::Done => Local::today().format("%Y-%m-%d").to_string(),
+			Resolution::Cancel => "true".to_owned(),
+		}
+	}
+
+	fn state(self) -> &'static str {
+		match self {
+			Resolution::Done => "✅ done",
+			Resolution::Cancel => "❌ canceled",
+		}
+	}
+
+	fn callback(self, note_id: String) -> Callback {
+		match self {
+			Resolution::Done => Callback::Done(note_id),
+			Resolution::Cancel => Callback::Cancel(note_id),
+		}
+	}
+}
+
+/// Largest number of tasks offered by `/done` and `/cancel`.
+const MAX_CHOICES: usize = 10;
+
+/// Close the open task best matching `query`, or offer a keyboard if several match equally well.
+async fn command_resolve(bot: &Bot, message_id: MessageId, query: &str, resolution: Resolution) -> Result<(), Error> {
+	let mut matches: Vec<_> = bot.backend.tasks().await?.into_iter()
+		.filter(|x| !x.repeats())
+		.filter_map(|x| fuzzy_match(query, &x.note.title).map(|score| (score, x)))
+		.collect();
+	let best = matches.iter().map(|x| x.0).min();
+	matches.retain(|x| Some(x.0) == best);
+	matches.sort_by_key(|x| x.1.todo_time);
+	match matches.len() {
+		0 => {
+			bot.messenger.reply(message_id, "No open task found").await?;
+		},
+		1 => {
+			let task = &matches[0].1;
+			bot.backend.set_label(&task.note.note_id, resolution.label(), &resolution.value()).await?;
+			bot.alerts_changed.notify_one();
+			bot.messenger.reply(message_id, &format!("{}: {}", resolution.state(), task.note.title)).await?;
+		},
+		n => {
+			let mut keyboard = Keyboard::new();
+			for (_, task) in matches.iter().take(MAX_CHOICES) {
+				keyboard.add_row(vec![resolution.callback(task.note.note_id.clone()).button(&task.note.title)]);
+			}
+			let mut text = format!("{} tasks match, which one?", n);
+			if n > MAX_CHOICES {
+				text += &format!(" (showing the first {})", MAX_CHOICES);
+			}
+			bot.messenger.send(&text, Format::Plain, Some(&keyboard)).await?;
+		}
+	}
+	Ok(())
+}
+
+/// Skip the next occurrence of the recurring reminder matching `query`, or offer a keyboard if several match.
+async fn command_skip(bot: &Bot, query: &str) -> Result<(), Error> {
+	let query = query.to_lowercase();
+	let matches: Vec<_> = bot.backend.tasks().await?.into_iter()
+		.filter(|x| x.recurrence.is_some() && x.note.title.to_lowercase().contains(&query))
+		.collect();
+	match matches.len() {
+		0 => {
+			bot.messenger.send("No recurring reminder found", Format::Plain, None).await?;
+		},
+		1 => {
+			let text = skip_next(bot, &matches[0].note.note_id).await?;
+			bot.messenger.send(&text, Format::Plain, None).await?;
+		},
+		_ => {
+			let mut keyboard = Keyboard::new();
+			for task in &matches {
+				keyboard.add_row(vec![Callback::Skip(task.note.note_id.clone()).button(&task.note.title)]);
+			}
+			bot.messenger.send("Which reminder should be skipped?", Format::Plain, Some(&keyboard)).await?;
+		}
+	}
+	Ok(())
+}
+
+/// Skip the next occurrence of a recurring reminder and describe the result.
+async fn skip_next(bot: &Bot, note_id: &str) -> Result<String, Error> {
+	let task = bot.backend.tasks().await?.into_iter()
+		.find(|x| x.note.note_id == note_id && x.recurrence.is_some());
+	let task = match task {
+		Some(task) => task,
+		None => return Ok("This recurring reminder has ended".to_owned()),
+	};
+	bot.backend.set_label(note_id, "skipUntil", &task.todo_time.format("%Y-%m-%dT%H:%M:%S").to_string()).await?;
+	bot.alerts_changed.notify_one();
+	let next = task.recurrence.as_ref().and_then(|x| x.next_after(task.todo_time + chrono::Duration::seconds(1)));
+	Ok(match next {
+		Some(next) => format!("Skipped {} on {}, next time is {}", task.note.title, format_date_time(bot, task.todo_time), format_date_time(bot, next)),
+		None => format!("Skipped {} on {}, that was the last time", task.note.title, format_date_time(bot, task.todo_time)),
+	})
+}
+
+/// Agenda of `days` days from `from`, `/week` starts with the number of overdue tasks.
+async fn send_agenda(bot: &Bot, from: NaiveDate, days: i64, show_overdue: bool) -> Result<(), Error> {
+	let tasks = bot.backend.tasks().await?;
+	let events = bot.backend.events().await?;
+	let mut html = String::new();
+	if show_overdue {
+		let now = Local::now();
+		let overdue = tasks.iter().filter(|x| x.is_overdue(now)).count();
+		if overdue > 0 {
+			html += &overdue_line(overdue);
+			html += "\n";
+		}
+	}
+	html += &agenda::render(&tasks, &events, from, days, bot.config.locale);
+	for message in split_message(&html) {
+		bot.messenger.send_message(&message).await?;
+	}
+	Ok(())
+}
+
+/// Largest number of tasks listed by `/overdue`, each is a separate message.
+const MAX_OVERDUE_LISTED: usize = 20;
+
+/// List overdue tasks, oldest first, each with buttons to reschedule it.
+async fn command_overdue(bot: &Bot) -> Result<(), Error> {
 	let now = Local::now();
-	client.post(&trilium_url("/api/clipper/notes"))
-		.header("Authorization", trilium_token)
-		.header("trilium-local-now-datetime", now.format("%Y-%m-%d %H:%M:%S%.3f%:z").to_string())
-		.json(&json!({ "title": title, "content": content, "clipType": "note" }))
-		.send().await?;
+	let mut overdue: Vec<_> = bot.backend.tasks().await?.into_iter().filter(|x| x.is_overdue(now)).collect();
+	if overdue.is_empty() {
+		bot.messenger.send("No overdue tasks :-)", Format::Plain, None).await?;
+		return Ok(());
+	}
+	overdue.sort_by_key(|x| x.todo_time);
+	bot.messenger.send_message(&format!("<b>{} overdue task(s)</b>", overdue.len())).await?;
+	for task in overdue.iter().take(MAX_OVERDUE_LISTED) {
+		let note_id = &task.note.note_id;
+		let mut keyboard = Keyboard::new();
+		keyboard.add_row(vec![
+			Callback::Reschedule(note_id.clone(), 0).button("Today"),
+			Callback::Reschedule(note_id.clone(), 1).button("Tomorrow"),
+			Callback::Reschedule(note_id.clone(), 7).button("Next week"),
+		]);
+		keyboard.add_row(vec![
+			Callback::Done(note_id.clone()).button("✅ Done"),
+			Callback::Cancel(note_id.clone()).button("❌ Cancel"),
+		]);
+		let text = format!("{} (due {})", task.note.title, format_date_time(bot, task.todo_time));
+		bot.messenger.send(&text, Format::Plain, Some(&keyboard)).await?;
+	}
+	if overdue.len() > MAX_OVERDUE_LISTED {
+		bot.messenger.send(&format!("… and {} more", overdue.len() - MAX_OVERDUE_LISTED), Format::Plain, None).await?;
+	}
 	Ok(())
 }
 
-async fn command_next() -> Result<(), Error> {
-	let events = request_event_alerts().await?;
-	let tasks = request_task_alerts().await?;
+async fn command_next(bot: &Bot) -> Result<(), Error> {
+	let events = bot.backend.events().await?;
+	let tasks = bot.backend.tasks().await?;
 	let mut all: Vec<_> = events.into_iter().map(EventOrTask::Event).chain(tasks.into_iter().map(EventOrTask::Task)).collect();
 	all.sort_by_key(|x| x.time());
 	let mut printed = 0;
 	let now = Local::now();
+	let overdue = all.iter().filter(|x| matches!(x, EventOrTask::Task(task) if task.is_overdue(now))).count();
 	let mut buf = "```\n".to_owned();
 	for x in all {
 		let time = x.time();
 		if time < now {
 			continue;
 		}
-		buf += &format!("{} {} {}\n", weekday_to_name(time.weekday()), time.format("%Y-%m-%d %H:%M").to_string(), x.description());
+		buf += &format!("{} {} {}\n", bot.config.locale.weekday_name(time.weekday()), time.format("%Y-%m-%d %H:%M"), x.description());
 		printed += 1;
 		if printed >= 10 {
 			break;
 		}
 	}
 	buf += "```\n";
-	send_message_markdown(buf).await?;
-	Ok(())
-}
-
-fn weekday_to_name(wd: Weekday) -> &'static str {
-	match wd {
-    	Weekday::Mon => "Mo",
-    	Weekday::Tue => "Di",
-    	Weekday::Wed => "Mi",
-    	Weekday::Thu => "Do",
-    	Weekday::Fri => "Fr",
-    	Weekday::Sat => "Sa",
-    	Weekday::Sun => "So",
+	if overdue > 0 {
+		buf += &format!("⚠️ {} overdue, see /overdue\n", overdue);
 	}
+	bot.messenger.send_message_markdown(&buf).await?;
+	Ok(())
 }
 
 enum EventOrTask {
-	Event(UsefulEvent),
-	Task(UsefulTask)
+	Event(Event),
+	Task(Task)
 }
 
 impl EventOrTask {
@@ -284,8 +874,8 @@ impl EventOrTask {
 	}
 	fn description(&self) -> &str {
 		match self {
-    		EventOrTask::Event(e) => &e.event.name,
-    		EventOrTask::Task(t) => &t.task.title,
+    		EventOrTask::Event(e) => &e.note.title,
+    		EventOrTask::Task(t) => &t.note.title,
 		}
 	}
 }
@@ -293,147 +883,172 @@ impl EventOrTask {
 // image note:
 // curl /api/clipper/clippings -H 'Accept: */*' -H 'Accept-Language: en' --compressed -H 'Content-Type: application/json' -H 'Authorization: icB3xohFDpkVt7YFpbTflUYC8pucmryVGpb1DFpd6ns=' -H 'Origin: moz-extension://13bc3fd7-5cb0-4d48-b368-76e389fd7c5f' --data $'{"title":"trilium/clipper.js at master \xb7 zadam/trilium","content":"<img src=\\"BoCpsLz9je8a01MdGbj4\\">","images":[{"imageId":"BoCpsLz9je8a01MdGbj4","src":"inline.png","dataUrl":"data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAASkAAAESCAYAAAChJCPsAAAgAElEQV"}]}'
 
-async fn event_alerts() {
+/// Alerts are delivered late if they are sent more than this after their time.
+const LATE_AFTER: i64 = 60;
+
+async fn alerts(bot: Arc<Bot>) {
+	let refresh_interval = chrono::Duration::from_std(bot.config.refresh_interval).unwrap();
+	let mut queue = AlertQueue::default();
+	// start of the last successful refresh, the queue may miss alerts of later changes
+	let mut refreshed = None;
+	let mut next_refresh = Local::now();
 	loop {
-		let last_min = Local::now().minute();
-		if let Err(e) = event_alerts_soon().await {
+		if Local::now() >= next_refresh {
+			let start = Local::now();
+			match refresh_alerts(&bot).await {
+				Ok(new_queue) => {
+					queue = new_queue;
+					refreshed = Some(start);
+				},
+				Err(e) => println!("error: {}", e),
+			}
+			next_refresh = Local::now() + refresh_interval;
+		}
+		if let Err(e) = send_due_alerts(&bot, &mut queue, refreshed).await {
 			println!("error: {}", e);
+			// the failed alert is queued again by the next refresh
+			next_refresh = Local::now();
+			tokio::time::sleep(Duration::from_secs(60)).await;
+			continue;
 		}
-		while Local::now().minute() == last_min {
-			tokio::time::sleep(Duration::from_secs(1)).await;
+		let wake = queue.next_time().map(|x| x.min(next_refresh)).unwrap_or(next_refresh);
+		let sleep = (wake - Local::now()).to_std().unwrap_or_default();
+		tokio::select! {
+			_ = tokio::time::sleep(sleep) => {},
+			_ = bot.alerts_changed.notified() => next_refresh = Local::now(),
 		}
-		tokio::time::sleep(Duration::from_secs(16)).await;
 	}
 }
 
-async fn request_event_alerts() -> Result<Vec<UsefulEvent>, Error> {
-	let text = CLIENT.get(&trilium_url("/custom/event_alerts")).send().await?.text().await?;
-	debug!("event_alerts response {}", text);
-	let events: Result<Vec<Event>, _> = serde_json::from_str(&text);
-	if events.is_err() {
-		eprintln!("failed to parse {}", text);
-	}
-	let events = events.map(|x| x.into_iter().flat_map(|event| {
-		let todo_time: DateTime<Local> = TimeZone::from_local_datetime(&Local,
-			&NaiveDateTime::parse_from_str(&event.start_time, "%Y-%m-%dT%H:%M:%S").ok()?).unwrap();
-		Some(UsefulEvent {
-			event,
-			todo_time
-		})
-	}).collect());
-	Ok(events?)
+/// Load tasks and events and queue their alerts that were not checked yet.
+async fn refresh_alerts(bot: &Bot) -> Result<AlertQueue, Error> {
+	let since = bot.state.read(|state| state.last_alert_check).unwrap_or_else(Local::now);
+	let tasks = bot.backend.tasks().await?;
+	let events = bot.backend.events().await?;
+	let queue = AlertQueue::new(&tasks, &events, &bot.config.lead_times, since);
+	debug!("alerts: {} tasks, {} events, {} alerts queued", tasks.len(), events.len(), queue.len());
+	Ok(queue)
 }
 
-async fn event_alerts_soon() -> Result<(), Error> {
+/// Send all queued alerts that are due now.
+///
+/// Alerts are only marked as checked up to the last successful refresh,
+/// so those missing from an outdated queue are sent once refreshing works again.
+async fn send_due_alerts(bot: &Bot, queue: &mut AlertQueue, refreshed: Option<DateTime<Local>>) -> Result<(), Error> {
 	let now = Local::now();
-
-	let events = request_event_alerts().await?;	
-	debug!("events_alerts: {} objects", events.len());
-	for event in events {
-		if event.todo_time <= now {
+	while let Some(alert) = queue.pop_due(now) {
+		let record = alert.record();
+		if bot.state.read(|state| state.sent_alerts.contains(&record)) {
 			continue;
 		}
-		let diff = event.todo_time - now;
-		let minutes = diff.num_minutes();
-		if minutes == 7 * 24 * 60 || minutes == 48 * 60 || minutes == 24 * 60 || minutes == 60 || minutes == 10 {
-			event_alert_notify(&format_time(diff), event.event).await?;
-		}
+		notify_alert(&*bot.messenger, &alert, alert.is_late(now, chrono::Duration::seconds(LATE_AFTER))).await?;
+		bot.state.update(|state| state.sent_alerts.insert(record))?;
+	}
+	if let Some(refreshed) = refreshed {
+		bot.state.update(|state| state.last_alert_check = Some(refreshed))?;
 	}
 	Ok(())
 }
 
-async fn event_alert_notify(time_left: &str, event: Event) -> Result<(), Error> {
-	API.send(SendMessage::new(*OWNER, format!("{}: {}", time_left, event.name))).await?;
-	Ok(())
+/// Messages sent once a day.
+#[derive(Debug, Clone, Copy)]
+enum Daily {
+	Digest,
+	Review,
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Event {
-	name: String,
-	start_time: String,
-}
+impl Daily {
+	fn last_sent(self, state: &State) -> Option<NaiveDate> {
+		match self {
+			Daily::Digest => state.last_digest,
+			Daily::Review => state.last_review,
+		}
+	}
 
-struct UsefulEvent {
-	event: Event,
-	todo_time: DateTime<Local>,
+	fn set_sent(self, state: &mut State, date: NaiveDate) {
+		match self {
+			Daily::Digest => state.last_digest = Some(date),
+			Daily::Review => state.last_review = Some(date),
+		}
+	}
 }
 
-async fn task_alerts() {
+/// Daily messages missed by less than this (e.g. during a restart) are sent late.
+const MAX_DAILY_DELAY: i64 = 60 * 60;
+
+async fn daily(bot: Arc<Bot>, time: NaiveTime, kind: Daily) {
+	let at = |date: NaiveDate| first_local_instant(date.and_time(time));
 	loop {
-		let last_min = Local::now().minute();
-		if let Err(e) = task_alerts_soon().await {
-			println!("error: {}", e);
+		let now = Local::now();
+		let today = now.date().naive_local();
+		let mut next = at(today);
+		let sent_today = bot.state.read(|state| kind.last_sent(state)) == Some(today);
+		if next <= now && (sent_today || now - next > chrono::Duration::seconds(MAX_DAILY_DELAY)) {
+			next = at(today + chrono::Duration::days(1));
 		}
-		while Local::now().minute() == last_min {
-			tokio::time::sleep(Duration::from_secs(1)).await;
+		tokio::time::sleep((next - Local::now()).to_std().unwrap_or_default()).await;
+		let result = match kind {
+			Daily::Digest => send_digest(&bot).await,
+			Daily::Review => send_review(&bot).await,
+		};
+		let result = result.and_then(|_| bot.state.update(|state| kind.set_sent(state, next.date().naive_local())));
+		if let Err(e) = result {
+			println!("error sending {:?}: {}", kind, e);
+			tokio::time::sleep(Duration::from_secs(60)).await;
 		}
-		tokio::time::sleep(Duration::from_secs(1)).await;
 	}
 }
 
-async fn request_task_alerts() -> Result<Vec<UsefulTask>, Error> {
-	let text = CLIENT.get(&trilium_url("/custom/task_alerts")).send().await?.text().await?;
-	debug!("task_alerts response {}", text);
-	let tasks: Result<Vec<Task>, _> = serde_json::from_str(&text);
-	if tasks.is_err() {
-		eprintln!("failed to parse {}", text);
-	}
-	let tasks = tasks.map(|tasks| tasks.into_iter().flat_map(|task| {
-		let mut todo_date = None;
-		let mut todo_time = None;
-		let mut is_reminder = false;
-		for attribute in &task.attributes {
-			if attribute.r#type != "label" {
-				continue;
-			}
-			match &*attribute.name {
-				"todoDate" => todo_date = Some(attribute.value.as_str().unwrap().to_owned()),
-				"todoTime" => todo_time = Some(attribute.value.as_str().unwrap().to_owned()),
-				"doneDate" => return None,
-				"reminder" => is_reminder = true,
-				"canceled" => if attribute.value.as_str().unwrap() == "true" { return None },
-				_ => {}
-			}
-		}
-		if todo_date.is_none() {
-			return None;
-		}
-		let todo_date = todo_date.unwrap();
-		let parts = todo_date.split('-').collect::<Vec<_>>();
-		let (year, month, day) = (parts[0].parse().unwrap(), parts[1].parse().unwrap(), parts[2].parse().unwrap());
-		let (hour, minute, second) = if let Some(todo_time) = todo_time {
-			let parts = todo_time.split(':').collect::<Vec<_>>();
-			(parts.get(0).map(|x| x.parse().unwrap()).unwrap_or(0), parts.get(1).map(|x| x.parse().unwrap()).unwrap_or(0), parts.get(2).map(|x| x.parse().unwrap()).unwrap_or(0))
-		} else { (0, 0, 0) };
-		let todo_time: DateTime<Local> = TimeZone::from_local_datetime(&Local, &NaiveDate::from_ymd(year, month, day).and_hms(hour, minute, second)).unwrap();
-		Some(UsefulTask {
-			task,
-			todo_time,
-			is_reminder
-		})
-		
-	}).collect());
-	Ok(tasks?)
-}
-
-async fn task_alerts_soon() -> Result<(), Error> {
-	let now = Local::now();
+/// Overdue tasks listed in the morning digest, the rest are only counted.
+const MAX_DIGEST_OVERDUE: usize = 5;
 
-	let tasks = request_task_alerts().await?;
-	debug!("task_alerts: {} objects", tasks.len());
-	for task in tasks {
-		if task.todo_time <= now {
-			continue;
+/// Today's agenda and overdue tasks.
+async fn send_digest(bot: &Bot) -> Result<(), Error> {
+	let tasks = bot.backend.tasks().await?;
+	let events = bot.backend.events().await?;
+	let now = Local::now();
+	let mut overdue: Vec<_> = tasks.iter().filter(|x| x.is_overdue(now)).collect();
+	overdue.sort_by_key(|x| x.todo_time);
+	let mut html = "<b>Good morning!</b>\n".to_owned();
+	if !overdue.is_empty() {
+		html += &overdue_line(overdue.len());
+		for task in overdue.iter().take(MAX_DIGEST_OVERDUE) {
+			html += &format!("☑️ {} (due {})\n", escape_html(&task.note.title), task.todo_time.format("%Y-%m-%d"));
 		}
-		let diff = task.todo_time - now;
-		let minutes = diff.num_minutes();
-		if !task.is_reminder && (minutes == 7 * 24 * 60 || minutes == 48 * 60 || minutes == 24 * 60 || minutes == 60 || minutes == 10) {
-			notify_owner(&format_time(diff), task.task).await?;
-		} else if task.is_reminder && minutes == 0 {
-			notify_owner("⏰", task.task).await?;
+		if overdue.len() > MAX_DIGEST_OVERDUE {
+			html += &format!("… and {} more\n", overdue.len() - MAX_DIGEST_OVERDUE);
 		}
 	}
+	html += "\n";
+	html += &agenda::render(&tasks, &events, Local::today().naive_local(), 1, bot.config.locale);
+	for message in split_message(&html) {
+		bot.messenger.send_message(&message).await?;
+	}
+	Ok(())
+}
+
+fn overdue_line(count: usize) -> String {
+	format!("⚠️ {} overdue task(s), see /overdue\n", count)
+}
+
+/// Tasks due today that are not done yet, each with buttons to finish or postpone it.
+async fn send_review(bot: &Bot) -> Result<(), Error> {
+	let today = Local::today().naive_local();
+	let open: Vec<_> = bot.backend.tasks().await?.into_iter()
+		.filter(|x| !x.is_reminder && x.todo_time.date().naive_local() == today)
+		.collect();
+	if open.is_empty() {
+		return Ok(());
+	}
+	bot.messenger.send_message(&format!("<b>{} open task(s) due today</b>", open.len())).await?;
+	for task in &open {
+		let mut keyboard = Keyboard::new();
+		keyboard.add_row(vec![
+			Callback::Done(task.note.note_id.clone()).button("✅ Done"),
+			Callback::Reschedule(task.note.note_id.clone(), 1).button("➡️ Tomorrow"),
+		]);
+		bot.messenger.send(&task.note.title, Format::Plain, Some(&keyboard)).await?;
+	}
 	Ok(())
 }
 
@@ -453,51 +1068,429 @@ fn format_time(diff: chrono::Duration) -> String {
 	}
 }
 
-async fn notify_owner(time_left: &str, task: Task) -> Result<(), Error> {
-	send_message(format!("{}: {}", time_left, task.title)).await
-}
-
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct Task {
-	attributes: Vec<Attribute>,
-	//contentLength: usize,
-	dateCreated: DateTime<FixedOffset>,
-	dateModified: DateTime<FixedOffset>,
-	deleteId: Option<serde_json::Value>,
-	// hash: String, // removed in 0.46
-	isContentAvailable: bool,
-	isDeleted: bool,
-	// isErased: i64, // removed in 0.46
-	isProtected: bool,
-	mime: String,
-	noteId: String,
-	title: String,
-	r#type: String,
-	// utcDateCreated: DateTime<Utc>, // removed in 0.46
-	utcDateModified: DateTime<Utc>,
-}
-
-struct UsefulTask {
-	task: Task,
-	todo_time: DateTime<Local>,
-	is_reminder: bool
-}
-
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct Attribute {
-	attributeId: String,
-	noteId: String,
-	r#type: String,
-	name: String,
-	value: serde_json::Value,
-	position: usize,
-	// utcDateCreated: DateTime<Utc>, // removed in 0.46
-	utcDateModified: DateTime<Utc>,
-	isDeleted: bool,
-	deleteId: Option<serde_json::Value>,
-	// hash: String, // removed in 0.46
-	isInheritable: bool,
-	//isOwned: bool, // removed in 0.42.2
+async fn notify_alert(messenger: &dyn Messenger, alert: &Alert, late: bool) -> Result<(), Error> {
+	let mut prefix = if alert.kind == AlertKind::Reminder {
+		"⏰".to_owned()
+	} else {
+		format_time(alert.lead)
+	};
+	if late {
+		prefix += &format!(" (late, due {})", alert.time().format("%Y-%m-%d %H:%M"));
+	}
+	let keyboard = match alert.kind {
+		AlertKind::Task | AlertKind::Reminder => Some(alert_keyboard(alert)),
+		AlertKind::Event => None,
+	};
+	messenger.send(&format!("{}: {}", prefix, alert.title), Format::Plain, keyboard.as_ref()).await?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use telegram_notes_bot::backend::MemoryBackend;
+	use telegram_notes_bot::messenger::{Button, RecordingMessenger, Sent};
+
+	struct TestBot {
+		bot: Arc<Bot>,
+		backend: Arc<MemoryBackend>,
+		messenger: Arc<RecordingMessenger>,
+	}
+
+	fn test_bot() -> TestBot {
+		let config = Config::from_toml(r#"
+			locale = "en"
+			[telegram]
+			bot_token = "123:abc"
+			owner = 1
+			[trilium]
+			host = "http://localhost:8080"
+			token = "token"
+		"#).unwrap();
+		let backend = Arc::new(MemoryBackend::new());
+		let messenger = Arc::new(RecordingMessenger::new());
+		let bot = Arc::new(Bot {
+			api: Arc::new(Api::new(&config.bot_token)),
+			config,
+			username: Some("notes_bot".to_owned()),
+			backend: Arc::clone(&backend) as Arc<dyn NotesBackend>,
+			messenger: Arc::clone(&messenger) as Arc<dyn Messenger>,
+			transcriber: None,
+			state: StateStore::in_memory(),
+			alerts_changed: Notify::new(),
+			media_groups: Mutex::new(HashMap::new()),
+		});
+		TestBot { bot, backend, messenger }
+	}
+
+	fn alert(kind: AlertKind, recurring: bool) -> Alert {
+		Alert {
+			kind,
+			note_id: "abc".to_owned(),
+			title: "water plants".to_owned(),
+			due: Local.ymd(2021, 5, 1).and_hms(18, 0, 0),
+			lead: chrono::Duration::hours(1),
+			recurring,
+		}
+	}
+
+	#[tokio::test]
+	async fn task_alert() {
+		let messenger = RecordingMessenger::new();
+		notify_alert(&messenger, &alert(AlertKind::Task, false), false).await.unwrap();
+		notify_alert(&messenger, &alert(AlertKind::Task, false), true).await.unwrap();
+		let sent = messenger.take();
+		let keyboard = Keyboard {
+			rows: vec![
+				vec![Button::callback("✅ Done", "done:abc"), Button::callback("❌ Cancel", "cancel:abc")],
+				vec![
+					Button::callback("💤 10m", "snooze_10m:abc"),
+					Button::callback("💤 1h", "snooze_1h:abc"),
+					Button::callback("💤 tomorrow", "snooze_tomorrow:abc"),
+				],
+			],
+		};
+		assert_eq!(sent[0], Sent::Message {
+			id: MessageId::new(1),
+			text: "1h: water plants".to_owned(),
+			format: Format::Plain,
+			keyboard: Some(keyboard),
+		});
+		match &sent[1] {
+			Sent::Message { text, .. } => assert_eq!(text, "1h (late, due 2021-05-01 17:00): water plants"),
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+
+	#[tokio::test]
+	async fn reminder_and_event_alerts() {
+		let messenger = RecordingMessenger::new();
+		notify_alert(&messenger, &alert(AlertKind::Reminder, true), false).await.unwrap();
+		notify_alert(&messenger, &alert(AlertKind::Event, false), false).await.unwrap();
+		let sent = messenger.take();
+		match &sent[0] {
+			Sent::Message { text, keyboard: Some(keyboard), .. } => {
+				assert_eq!(text, "⏰: water plants");
+				// occurrences of recurring reminders can only be snoozed
+				assert_eq!(keyboard.rows.len(), 1);
+			},
+			x => panic!("unexpected {:?}", x),
+		}
+		match &sent[1] {
+			Sent::Message { text, keyboard, .. } => {
+				assert_eq!(text, "1h: water plants");
+				assert!(keyboard.is_none());
+			},
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+
+	#[tokio::test]
+	async fn reminder_draft() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let start = Local::now() + chrono::Duration::days(1);
+		let start = start.with_second(0).unwrap().with_nanosecond(0).unwrap();
+		run_command(&bot, MessageId::new(100), Command::RemindMe { time: Some(start), text: Some("call mom".to_owned()) }).await.unwrap();
+		let sent = messenger.take();
+		let id = match &sent[..] {
+			[Sent::Message { id, keyboard: None, .. }, Sent::Edit { id: edited, keyboard: Some(keyboard), .. }] => {
+				assert_eq!(id, edited);
+				assert_eq!(keyboard, &get_keyboard(*id));
+				*id
+			},
+			x => panic!("unexpected {:?}", x),
+		};
+
+		let draft = bot.state.read(|state| state.draft(id).cloned()).unwrap();
+		extend_draft(&bot, draft, chrono::Duration::hours(1)).await.unwrap();
+		let draft = bot.state.read(|state| state.draft(id).cloned()).unwrap();
+		extend_draft(&bot, draft, chrono::Duration::minutes(10)).await.unwrap();
+		match &messenger.take()[..] {
+			[_, Sent::Edit { text, .. }] => assert!(text.ends_with(" (+1h10m): call mom"), "{}", text),
+			x => panic!("unexpected {:?}", x),
+		}
+
+		let draft = bot.state.read(|state| state.draft(id).cloned()).unwrap();
+		assert_eq!(draft.time(), start + chrono::Duration::minutes(70));
+		save_draft(&bot, draft).await.unwrap();
+		assert!(bot.state.read(|state| state.draft(id).is_none()));
+		let tasks = backend.tasks().await.unwrap();
+		assert_eq!(tasks.len(), 1);
+		assert_eq!(tasks[0].note.title, "call mom");
+		assert_eq!(tasks[0].todo_time, start + chrono::Duration::minutes(70));
+		assert!(tasks[0].is_reminder);
+	}
+
+	#[tokio::test]
+	async fn alert_check_after_refresh() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let before = Local::now() - chrono::Duration::minutes(5);
+		bot.state.update(|state| state.last_alert_check = Some(before)).unwrap();
+		// refreshing failed so far
+		send_due_alerts(&bot, &mut AlertQueue::default(), None).await.unwrap();
+		assert_eq!(bot.state.read(|state| state.last_alert_check), Some(before));
+
+		let due = Local::now() - chrono::Duration::minutes(1);
+		backend.add_task("missed", &[("todoDate", &due.format("%Y-%m-%d").to_string()), ("todoTime", &due.format("%H:%M:%S").to_string())], true);
+		let refreshed = Local::now();
+		let mut queue = refresh_alerts(&bot).await.unwrap();
+		send_due_alerts(&bot, &mut queue, Some(refreshed)).await.unwrap();
+		assert_eq!(bot.state.read(|state| state.last_alert_check), Some(refreshed));
+		match &messenger.take()[..] {
+			[Sent::Message { text, .. }] => assert!(text.ends_with(": missed"), "{}", text),
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+
+	#[tokio::test]
+	async fn alert_sent_once() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let due = Local::now() + chrono::Duration::minutes(5);
+		backend.add_task("pay rent", &[("todoDate", &due.format("%Y-%m-%d").to_string()), ("todoTime", &due.format("%H:%M:%S").to_string())], false);
+		let since = Local::now() - chrono::Duration::minutes(10);
+		for _ in 0..2 {
+			// e.g. a restart before the check time was saved
+			bot.state.update(|state| state.last_alert_check = Some(since)).unwrap();
+			let mut queue = refresh_alerts(&bot).await.unwrap();
+			send_due_alerts(&bot, &mut queue, None).await.unwrap();
+		}
+		match &messenger.take()[..] {
+			[Sent::Message { text, .. }] => assert!(text.starts_with("10m (late") && text.ends_with(": pay rent"), "{}", text),
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+
+	#[tokio::test]
+	async fn snooze_daily_reminder() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let note_id = backend.add_daily_reminder("stretch", "08:00:00");
+		let tasks = backend.tasks().await.unwrap();
+		let due = tasks[0].todo_time;
+		let mut queue = AlertQueue::new(&tasks, &[], &bot.config.lead_times, due - chrono::Duration::minutes(1));
+		let alert = queue.pop_due(due).unwrap();
+		assert!(alert.recurring);
+		notify_alert(&*bot.messenger, &alert, false).await.unwrap();
+		match &messenger.take()[..] {
+			// daily reminders can only be snoozed
+			[Sent::Message { keyboard: Some(keyboard), .. }] => assert_eq!(keyboard.rows.len(), 1),
+			x => panic!("unexpected {:?}", x),
+		}
+
+		let time = snooze_task(&bot, &note_id, Snooze::For(chrono::Duration::minutes(10))).await.unwrap();
+		let tasks = backend.tasks().await.unwrap();
+		assert_eq!(tasks.len(), 2);
+		let daily = tasks.iter().find(|x| x.note.note_id == note_id).unwrap();
+		assert_eq!(daily.todo_time, due);
+		let snoozed = tasks.iter().find(|x| x.note.note_id != note_id).unwrap();
+		assert_eq!(snoozed.note.title, "stretch");
+		assert_eq!(snoozed.todo_time, time);
+		assert!(snoozed.is_reminder && !snoozed.repeats());
+	}
+
+	#[tokio::test]
+	async fn snooze_skips_passed_alerts() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let due = Local::now() + chrono::Duration::minutes(5);
+		let note_id = backend.add_task("pay rent", &[("todoDate", &due.format("%Y-%m-%d").to_string()), ("todoTime", &due.format("%H:%M:%S").to_string())], false);
+		let time = snooze_task(&bot, &note_id, Snooze::For(chrono::Duration::minutes(10))).await.unwrap();
+		assert_eq!(backend.tasks().await.unwrap()[0].todo_time, time);
+
+		bot.state.update(|state| state.last_alert_check = Some(Local::now() - chrono::Duration::minutes(1))).unwrap();
+		let mut queue = refresh_alerts(&bot).await.unwrap();
+		send_due_alerts(&bot, &mut queue, None).await.unwrap();
+		// the 10m alert is not sent right after snoozing for 10m
+		assert_eq!(messenger.take(), Vec::new());
+		assert_eq!(queue.next_time(), None);
+	}
+
+	#[tokio::test]
+	async fn resolve_command() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let date = Local::today().format("%Y-%m-%d").to_string();
+		let rent = backend.add_task("pay rent", &[("todoDate", &date), ("todoTime", "12:00:00")], false);
+		let tax = backend.add_task("pay taxes", &[("todoDate", &date), ("todoTime", "10:00:00")], false);
+		backend.add_daily_reminder("pay attention", "08:00:00");
+		backend.add_task("pay bills", &[("recurrence", "FREQ=DAILY;BYHOUR=9;BYMINUTE=0")], true);
+		command_resolve(&bot, MessageId::new(100), "pay", Resolution::Done).await.unwrap();
+		let keyboard = Keyboard {
+			rows: vec![
+				vec![Button::callback("pay taxes", format!("done:{}", tax))],
+				vec![Button::callback("pay rent", format!("done:{}", rent))],
+			],
+		};
+		assert_eq!(messenger.take(), vec![Sent::Message {
+			id: MessageId::new(1),
+			text: "2 tasks match, which one?".to_owned(),
+			format: Format::Plain,
+			keyboard: Some(keyboard),
+		}]);
+
+		command_resolve(&bot, MessageId::new(101), "attention", Resolution::Cancel).await.unwrap();
+		match &messenger.take()[..] {
+			[Sent::Reply { text, .. }] => assert_eq!(text, "No open task found"),
+			x => panic!("unexpected {:?}", x),
+		}
+		command_resolve(&bot, MessageId::new(102), "rent", Resolution::Done).await.unwrap();
+		assert!(backend.tasks().await.unwrap().iter().all(|x| x.note.note_id != rent));
+	}
+
+	#[tokio::test]
+	async fn transcribed_audio() {
+		let TestBot { mut bot, backend, messenger } = test_bot();
+		let command = ["sh", "-c", "cat {file}"].iter().map(|x| x.to_string()).collect();
+		let dir = std::env::temp_dir().join(format!("telegram_notes_bot-{}-audio", process::id()));
+		Arc::get_mut(&mut bot).unwrap().transcriber = Some(Box::new(CommandTranscriber::new(command, dir.clone())));
+		save_audio_data(&bot, MessageId::new(10), b"buy milk\n".to_vec(), "voice message", "audio/ogg", None).await.unwrap();
+		assert_eq!(*backend.notes.lock().unwrap(), [("voice message".to_owned(), "buy milk".to_owned())]);
+		assert_eq!(*backend.files.lock().unwrap(), [("voice message.ogg".to_owned(), "audio/ogg".to_owned(), b"buy milk\n".to_vec())]);
+		assert_eq!(messenger.take(), [Sent::Reply { id: MessageId::new(1), to: MessageId::new(10), text: "Audio saved and transcribed :-)".to_owned() }]);
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
+	#[tokio::test]
+	async fn digest() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let date = |days| (Local::today() + chrono::Duration::days(days)).format("%Y-%m-%d").to_string();
+		for days in 1..=MAX_DIGEST_OVERDUE as i64 + 2 {
+			backend.add_task(&format!("task <{}>", days), &[("todoDate", &date(-days))], false);
+		}
+		backend.add_task("today", &[("todoDate", &date(0))], false);
+		send_digest(&bot).await.unwrap();
+		let text = match &messenger.take()[..] {
+			[Sent::Message { text, format: Format::Html, .. }] => text.clone(),
+			x => panic!("unexpected {:?}", x),
+		};
+		let mut expected = "<b>Good morning!</b>\n⚠️ 7 overdue task(s), see /overdue\n".to_owned();
+		for days in (3..=7).rev() {
+			expected += &format!("☑️ task &lt;{}&gt; (due {})\n", days, date(-days));
+		}
+		expected += "… and 2 more\n\n";
+		assert!(text.starts_with(&expected), "{}", text);
+		assert!(text.ends_with("☑️ today"), "{}", text);
+	}
+
+	#[tokio::test]
+	async fn week() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let date = |days| (Local::today() + chrono::Duration::days(days)).format("%Y-%m-%d").to_string();
+		backend.add_task("late", &[("todoDate", &date(-2))], false);
+		backend.add_task("later", &[("todoDate", &date(-1))], false);
+		backend.add_task("soon", &[("todoDate", &date(3))], false);
+		run_command(&bot, MessageId::new(100), Command::Week { weeks: 1 }).await.unwrap();
+		let text = match &messenger.take()[..] {
+			[Sent::Message { text, format: Format::Html, .. }] => text.clone(),
+			x => panic!("unexpected {:?}", x),
+		};
+		assert!(text.starts_with("⚠️ 2 overdue task(s), see /overdue\n\n"), "{}", text);
+		assert!(text.contains("☑️ soon"), "{}", text);
+		run_command(&bot, MessageId::new(101), Command::Today).await.unwrap();
+		match &messenger.take()[..] {
+			[Sent::Message { text, .. }] => assert!(!text.contains("overdue"), "{}", text),
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+
+	fn attachment(title: &str) -> Attachment {
+		Attachment {
+			title: title.to_owned(),
+			file_name: format!("{}.jpg", title),
+			mime: "image/jpeg".to_owned(),
+			data: vec![1, 2, 3],
+		}
+	}
+
+	#[tokio::test]
+	async fn forwarded_album() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let source = Source {
+			name: "Channel".to_owned(),
+			url: Some("https://t.me/channel/1".to_owned()),
+			date: Local.ymd(2021, 5, 1).and_hms(12, 0, 0),
+			key: "channel1".to_owned(),
+		};
+		let items = vec![
+			MediaGroupItem { message_id: MessageId::new(10), caption: None, attachment: attachment("photo") },
+			MediaGroupItem { message_id: MessageId::new(11), caption: Some("Trip\nday one".to_owned()), attachment: attachment("photo") },
+		];
+		bot.media_groups.lock().unwrap().insert("group".to_owned(), MediaGroup {
+			items,
+			downloading: 0,
+			last_part: Instant::now() - MEDIA_GROUP_DELAY,
+			source: Some(source.clone()),
+		});
+		save_media_group(Arc::clone(&bot), "group".to_owned()).await;
+
+		assert_eq!(*backend.notes.lock().unwrap(), [(
+			"Trip".to_owned(),
+			source.header_html() + "<p>Trip\nday one</p>",
+		)]);
+		let files: Vec<_> = backend.files.lock().unwrap().iter().map(|x| x.0.clone()).collect();
+		assert_eq!(files, ["photo 1", "Trip"]);
+		assert_eq!(*backend.forwarded.lock().unwrap(), [("Trip".to_owned(), source)]);
+		assert_eq!(messenger.take(), [Sent::Reply { id: MessageId::new(1), to: MessageId::new(10), text: "Album with 2 files saved :-)".to_owned() }]);
+	}
+
+	#[tokio::test]
+	async fn album_with_slow_download() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let (finish_download, download) = tokio::sync::oneshot::channel();
+		let slow = {
+			let bot = Arc::clone(&bot);
+			task::spawn(async move {
+				let attachment = async { Ok(download.await.unwrap()) };
+				add_to_media_group(&bot, "group", MessageId::new(10), Some("first".to_owned()), None, attachment).await
+			})
+		};
+		// the slow part starts downloading first
+		tokio::time::sleep(Duration::from_millis(10)).await;
+		add_to_media_group(&bot, "group", MessageId::new(11), None, None, async { Ok(attachment("fast")) }).await.unwrap();
+		{
+			let mut media_groups = bot.media_groups.lock().unwrap();
+			let media_group = media_groups.get_mut("group").unwrap();
+			assert_eq!((media_group.items.len(), media_group.downloading), (1, 1));
+			// the album stays open, however long the download takes
+			media_group.last_part = Instant::now() - 2 * MEDIA_GROUP_DELAY;
+		}
+		finish_download.send(attachment("slow")).unwrap();
+		slow.await.unwrap().unwrap();
+		{
+			let mut media_groups = bot.media_groups.lock().unwrap();
+			let media_group = media_groups.get_mut("group").unwrap();
+			assert_eq!((media_group.items.len(), media_group.downloading), (2, 0));
+			media_group.last_part = Instant::now() - MEDIA_GROUP_DELAY;
+		}
+		save_media_group(Arc::clone(&bot), "group".to_owned()).await;
+		assert!(bot.media_groups.lock().unwrap().is_empty());
+		assert_eq!(*backend.notes.lock().unwrap(), [("first".to_owned(), "<p>first</p>".to_owned())]);
+		let files: Vec<_> = backend.files.lock().unwrap().iter().map(|x| x.0.clone()).collect();
+		assert_eq!(files, ["first", "fast 2"]);
+		assert_eq!(messenger.take(), [Sent::Reply { id: MessageId::new(1), to: MessageId::new(10), text: "Album with 2 files saved :-)".to_owned() }]);
+	}
+
+	#[tokio::test]
+	async fn album_download_error() {
+		let TestBot { bot, backend, .. } = test_bot();
+		add_to_media_group(&bot, "group", MessageId::new(10), None, None, async { Ok(attachment("photo")) }).await.unwrap();
+		assert!(add_to_media_group(&bot, "group", MessageId::new(11), None, None, async { Err(error("download failed")) }).await.is_err());
+		bot.media_groups.lock().unwrap().get_mut("group").unwrap().last_part = Instant::now() - MEDIA_GROUP_DELAY;
+		save_media_group(Arc::clone(&bot), "group".to_owned()).await;
+		assert_eq!(backend.files.lock().unwrap().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn next() {
+		let TestBot { bot, backend, messenger } = test_bot();
+		let date = |days| (Local::today() + chrono::Duration::days(days)).naive_local();
+		backend.add_task("overdue", &[("todoDate", &date(-2).format("%Y-%m-%d").to_string())], false);
+		backend.add_task("task", &[("todoDate", &date(2).format("%Y-%m-%d").to_string()), ("todoTime", "10:00:00")], false);
+		backend.add_event("event", &[("startTime", &date(1).and_hms(12, 30, 0).format("%Y-%m-%dT%H:%M:%S").to_string())]);
+		command_next(&bot).await.unwrap();
+		let line = |days, time, title| format!("{} {} {} {}", bot.config.locale.weekday_name(date(days).weekday()), date(days).format("%Y-%m-%d"), time, title);
+		let expected = format!("```\n{}\n{}\n```\n⚠️ 1 overdue, see /overdue\n", line(1, "12:30", "event"), line(2, "10:00", "task"));
+		match &messenger.take()[..] {
+			[Sent::Message { text, format: Format::MarkdownV2, .. }] => assert_eq!(text, &expected),
+			x => panic!("unexpected {:?}", x),
+		}
+	}
 }
@@ -0,0 +1,255 @@
+//! Sending messages to the owner of the bot.
+//!
+//! [`TelegramMessenger`] is used by the bot, [`RecordingMessenger`] records
+//! everything that would be sent so it can be checked without a bot token.
+
+use async_trait::async_trait;
+use telegram_bot::{Api, CallbackQuery, CanAnswerCallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode, UserId};
+use telegram_bot::types::{EditMessageText, SendMessage};
+use telegram_bot::types::refs::ToMessageId;
+
+use std::sync::{Arc, Mutex};
+
+use crate::Error;
+
+/// Longest message Telegram accepts, in UTF-16 code units.
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// Split a long message at line breaks into messages Telegram accepts.
+/// Formatting must not span multiple lines.
+pub fn split_message(text: &str) -> Vec<String> {
+	let mut messages = vec![String::new()];
+	for line in text.lines() {
+		let current = messages.last_mut().unwrap();
+		if !current.is_empty() && current.encode_utf16().count() + line.encode_utf16().count() + 1 > MAX_MESSAGE_LENGTH {
+			messages.push(String::new());
+		}
+		let current = messages.last_mut().unwrap();
+		if !current.is_empty() {
+			current.push('\n');
+		}
+		current.push_str(line);
+	}
+	messages
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	Plain,
+	Html,
+	MarkdownV2,
+}
+
+impl Format {
+	fn parse_mode(self) -> Option<ParseMode> {
+		match self {
+			Format::Plain => None,
+			Format::Html => Some(ParseMode::Html),
+			Format::MarkdownV2 => Some(ParseMode::MarkdownV2),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Button {
+	pub text: String,
+	pub callback_data: String,
+}
+
+impl Button {
+	pub fn callback<S: Into<String>, T: Into<String>>(text: S, callback_data: T) -> Self {
+		Button {
+			text: text.into(),
+			callback_data: callback_data.into(),
+		}
+	}
+}
+
+/// Inline keyboard attached to a message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keyboard {
+	pub rows: Vec<Vec<Button>>,
+}
+
+impl Keyboard {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn add_row(&mut self, row: Vec<Button>) {
+		self.rows.push(row);
+	}
+}
+
+impl From<&Keyboard> for InlineKeyboardMarkup {
+	fn from(keyboard: &Keyboard) -> Self {
+		let mut markup = InlineKeyboardMarkup::new();
+		for row in &keyboard.rows {
+			markup.add_row(row.iter().map(|x| InlineKeyboardButton::callback(&x.text, &x.callback_data)).collect());
+		}
+		markup
+	}
+}
+
+#[async_trait]
+pub trait Messenger: Send + Sync {
+	/// Send a message to the owner and return its ID.
+	async fn send(&self, text: &str, format: Format, keyboard: Option<&Keyboard>) -> Result<MessageId, Error>;
+	/// Replace text and keyboard of a message sent earlier.
+	async fn edit(&self, message: MessageId, text: &str, format: Format, keyboard: Option<&Keyboard>) -> Result<(), Error>;
+	/// Send a plain text reply to a message of the owner.
+	async fn reply(&self, to: MessageId, text: &str) -> Result<MessageId, Error>;
+	/// Acknowledge a callback query, optionally showing a short notification.
+	async fn answer_callback(&self, query: &CallbackQuery, text: Option<&str>) -> Result<(), Error>;
+
+	async fn send_message(&self, text: &str) -> Result<(), Error> {
+		self.send(text, Format::Html, None).await?;
+		Ok(())
+	}
+
+	async fn send_message_markdown(&self, text: &str) -> Result<(), Error> {
+		self.send(text, Format::MarkdownV2, None).await?;
+		Ok(())
+	}
+}
+
+pub struct TelegramMessenger {
+	api: Arc<Api>,
+	owner: UserId,
+}
+
+impl TelegramMessenger {
+	pub fn new(api: Arc<Api>, owner: UserId) -> Self {
+		TelegramMessenger { api, owner }
+	}
+}
+
+#[async_trait]
+impl Messenger for TelegramMessenger {
+	async fn send(&self, text: &str, format: Format, keyboard: Option<&Keyboard>) -> Result<MessageId, Error> {
+		let mut msg = SendMessage::new(self.owner, text);
+		if let Some(parse_mode) = format.parse_mode() {
+			msg.parse_mode(parse_mode);
+		}
+		if let Some(keyboard) = keyboard {
+			msg.reply_markup(InlineKeyboardMarkup::from(keyboard));
+		}
+		Ok(self.api.send(msg).await?.to_message_id())
+	}
+
+	async fn edit(&self, message: MessageId, text: &str, format: Format, keyboard: Option<&Keyboard>) -> Result<(), Error> {
+		let mut edit = EditMessageText::new(self.owner, message, text);
+		if let Some(parse_mode) = format.parse_mode() {
+			edit.parse_mode(parse_mode);
+		}
+		if let Some(keyboard) = keyboard {
+			edit.reply_markup(InlineKeyboardMarkup::from(keyboard));
+		}
+		self.api.send(edit).await?;
+		Ok(())
+	}
+
+	async fn reply(&self, to: MessageId, text: &str) -> Result<MessageId, Error> {
+		let mut msg = SendMessage::new(self.owner, text);
+		msg.reply_to(to);
+		Ok(self.api.send(msg).await?.to_message_id())
+	}
+
+	async fn answer_callback(&self, query: &CallbackQuery, text: Option<&str>) -> Result<(), Error> {
+		let answer = match text {
+			Some(text) => query.answer(text),
+			None => query.acknowledge(),
+		};
+		self.api.send(answer).await?;
+		Ok(())
+	}
+}
+
+/// Something a [`RecordingMessenger`] was asked to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sent {
+	Message { id: MessageId, text: String, format: Format, keyboard: Option<Keyboard> },
+	Edit { id: MessageId, text: String, format: Format, keyboard: Option<Keyboard> },
+	Reply { id: MessageId, to: MessageId, text: String },
+	CallbackAnswer { text: Option<String> },
+}
+
+/// Test double that records all messages instead of sending them.
+#[derive(Default)]
+pub struct RecordingMessenger {
+	pub sent: Mutex<Vec<Sent>>,
+	next_id: Mutex<i64>,
+}
+
+impl RecordingMessenger {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn next_id(&self) -> MessageId {
+		let mut next_id = self.next_id.lock().unwrap();
+		*next_id += 1;
+		MessageId::new(*next_id)
+	}
+
+	/// Remove and return everything recorded so far.
+	pub fn take(&self) -> Vec<Sent> {
+		std::mem::take(&mut *self.sent.lock().unwrap())
+	}
+}
+
+#[async_trait]
+impl Messenger for RecordingMessenger {
+	async fn send(&self, text: &str, format: Format, keyboard: Option<&Keyboard>) -> Result<MessageId, Error> {
+		let id = self.next_id();
+		self.sent.lock().unwrap().push(Sent::Message { id, text: text.to_owned(), format, keyboard: keyboard.cloned() });
+		Ok(id)
+	}
+
+	async fn edit(&self, message: MessageId, text: &str, format: Format, keyboard: Option<&Keyboard>) -> Result<(), Error> {
+		self.sent.lock().unwrap().push(Sent::Edit { id: message, text: text.to_owned(), format, keyboard: keyboard.cloned() });
+		Ok(())
+	}
+
+	async fn reply(&self, to: MessageId, text: &str) -> Result<MessageId, Error> {
+		let id = self.next_id();
+		self.sent.lock().unwrap().push(Sent::Reply { id, to, text: text.to_owned() });
+		Ok(id)
+	}
+
+	async fn answer_callback(&self, _query: &CallbackQuery, text: Option<&str>) -> Result<(), Error> {
+		self.sent.lock().unwrap().push(Sent::CallbackAnswer { text: text.map(|x| x.to_owned()) });
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn split_at_limit() {
+		let line = "a".repeat(MAX_MESSAGE_LENGTH - 2);
+		// the line break counts too
+		let text = format!("{}\nb", line);
+		assert_eq!(split_message(&text), vec![text.clone()]);
+		let text = format!("{}\nbc", line);
+		assert_eq!(split_message(&text), vec![line.clone(), "bc".to_owned()]);
+	}
+
+	#[test]
+	fn split_counts_utf16() {
+		// each emoji is two UTF-16 code units
+		let line = "😀".repeat(MAX_MESSAGE_LENGTH / 2 - 1);
+		let text = format!("{}\nb", line);
+		assert_eq!(split_message(&text), vec![text.clone()]);
+		let text = format!("{}\n😀", line);
+		assert_eq!(split_message(&text), vec![line.clone(), "😀".to_owned()]);
+	}
+
+	#[test]
+	fn split_short() {
+		assert_eq!(split_message("a\nb\n\nc"), vec!["a\nb\n\nc".to_owned()]);
+		assert_eq!(split_message(""), vec![String::new()]);
+	}
+}
@@ -0,0 +1,287 @@
+//! Metadata of web pages for notes of shared links.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Client;
+
+use std::time::Duration;
+
+use crate::{escape_html, Error};
+
+/// Requests for pages (including reading the page) are aborted after this time.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Only the start of larger pages is read.
+const MAX_PAGE_SIZE: usize = 1024 * 1024;
+/// Length of the excerpt of the page text, in characters.
+const EXCERPT_LENGTH: usize = 500;
+/// Shorter paragraphs (e.g. navigation) are not part of the excerpt.
+const MIN_PARAGRAPH_LENGTH: usize = 80;
+
+static TITLE_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap()
+});
+
+static META_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"(?is)<meta\s[^>]*>").unwrap()
+});
+
+static ATTRIBUTE_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r#"(?s)([a-zA-Z:_-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap()
+});
+
+static PARAGRAPH_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"(?is)<p[\s>].*?</p>").unwrap()
+});
+
+static TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"(?s)<[^>]*>").unwrap()
+});
+
+static ENTITY_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap()
+});
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageInfo {
+	pub title: Option<String>,
+	pub description: Option<String>,
+	pub site_name: Option<String>,
+	/// Start of the text of the page.
+	pub excerpt: Option<String>,
+}
+
+impl PageInfo {
+	/// Parse the title, OpenGraph properties, description and an excerpt of a HTML page.
+	pub fn parse(html: &str) -> PageInfo {
+		let mut info = PageInfo::default();
+		let mut title = None;
+		let mut description = None;
+		for meta in META_REGEX.find_iter(html) {
+			let mut key = None;
+			let mut content = None;
+			for attribute in ATTRIBUTE_REGEX.captures_iter(meta.as_str()) {
+				let value = attribute.get(2).or_else(|| attribute.get(3)).or_else(|| attribute.get(4)).map_or("", |x| x.as_str());
+				match &*attribute[1].to_lowercase() {
+					"property" | "name" => key = Some(value.to_lowercase()),
+					"content" => content = Some(text(value)),
+					_ => {}
+				}
+			}
+			let content = match content.filter(|x| !x.is_empty()) {
+				Some(x) => x,
+				None => continue,
+			};
+			match key.as_deref() {
+				Some("og:title") => info.title = Some(content),
+				Some("og:description") => info.description = Some(content),
+				Some("og:site_name") => info.site_name = Some(content),
+				Some("description") => description = Some(content),
+				Some("twitter:title") => title = title.or(Some(content)),
+				_ => {}
+			}
+		}
+		if let Some(x) = TITLE_REGEX.captures(html) {
+			title = Some(text(&x[1])).filter(|x| !x.is_empty()).or(title);
+		}
+		info.title = info.title.or(title);
+		info.description = info.description.or(description);
+
+		let mut excerpt = String::new();
+		for paragraph in PARAGRAPH_REGEX.find_iter(html) {
+			let paragraph = text(paragraph.as_str());
+			if paragraph.chars().count() < MIN_PARAGRAPH_LENGTH {
+				continue;
+			}
+			if !excerpt.is_empty() {
+				excerpt.push(' ');
+			}
+			excerpt += &paragraph;
+			if excerpt.chars().count() >= EXCERPT_LENGTH {
+				excerpt = excerpt.chars().take(EXCERPT_LENGTH).collect::<String>() + "…";
+				break;
+			}
+		}
+		info.excerpt = Some(excerpt).filter(|x| !x.is_empty());
+		info
+	}
+
+	/// Note content with the link, the description and the excerpt.
+	pub fn to_html(&self, url: &str) -> String {
+		let mut html = format!("<p><a href=\"{0}\">{0}</a>", escape_html(url));
+		if let Some(site_name) = &self.site_name {
+			html += &format!(" ({})", escape_html(site_name));
+		}
+		html += "</p>";
+		if let Some(description) = &self.description {
+			html += &format!("<p>{}</p>", escape_html(description));
+		}
+		if let Some(excerpt) = &self.excerpt {
+			html += &format!("<blockquote>{}</blockquote>", escape_html(excerpt));
+		}
+		html
+	}
+}
+
+/// Download a page and parse its metadata.
+///
+/// Only the first [`MAX_PAGE_SIZE`] bytes are read, pages that are not HTML have no metadata.
+pub async fn fetch(client: &Client, url: &str) -> Result<PageInfo, Error> {
+	fetch_with_timeout(client, url, FETCH_TIMEOUT).await
+}
+
+async fn fetch_with_timeout(client: &Client, url: &str, timeout: Duration) -> Result<PageInfo, Error> {
+	let mut resp = client.get(url).timeout(timeout).send().await?.error_for_status()?;
+	let is_html = resp.headers().get(reqwest::header::CONTENT_TYPE)
+		.and_then(|x| x.to_str().ok())
+		.is_some_and(|x| x.contains("html"));
+	if !is_html {
+		return Ok(PageInfo::default());
+	}
+	let mut data = Vec::new();
+	while let Some(chunk) = resp.chunk().await? {
+		data.extend_from_slice(&chunk);
+		if data.len() >= MAX_PAGE_SIZE {
+			data.truncate(MAX_PAGE_SIZE);
+			break;
+		}
+	}
+	Ok(PageInfo::parse(&String::from_utf8_lossy(&data)))
+}
+
+/// Text of a HTML fragment without tags, entities decoded and whitespace collapsed.
+fn text(html: &str) -> String {
+	let html = TAG_REGEX.replace_all(html, " ");
+	let text = ENTITY_REGEX.replace_all(&html, |x: &regex::Captures| {
+		let entity = &x[1];
+		let c = match entity {
+			"amp" => Some('&'),
+			"lt" => Some('<'),
+			"gt" => Some('>'),
+			"quot" => Some('"'),
+			"apos" => Some('\''),
+			"nbsp" => Some(' '),
+			_ if entity.starts_with("#x") || entity.starts_with("#X") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+			_ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+			_ => None,
+		};
+		c.map_or_else(|| x[0].to_owned(), String::from)
+	});
+	text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+
+	#[test]
+	fn metadata() {
+		let html = r#"<html><head>
+			<meta property="og:title" content="OpenGraph &amp; title">
+			<title>Page title</title>
+			<meta name="description" content='Plain description'>
+			<meta property=og:site_name content=Example>
+			<meta name="twitter:title" content="Twitter title">
+		</head></html>"#;
+		assert_eq!(PageInfo::parse(html), PageInfo {
+			title: Some("OpenGraph & title".to_owned()),
+			description: Some("Plain description".to_owned()),
+			site_name: Some("Example".to_owned()),
+			excerpt: None,
+		});
+
+		let html = r#"<meta name="twitter:title" content="Twitter title"><meta property="og:description" content="">"#;
+		assert_eq!(PageInfo::parse(html).title.as_deref(), Some("Twitter title"));
+		assert_eq!(PageInfo::parse(html).description, None);
+		let html = r#"<meta name="twitter:title" content="Twitter title"><TITLE lang="en">
+			Page   title </TITLE>"#;
+		assert_eq!(PageInfo::parse(html).title.as_deref(), Some("Page title"));
+	}
+
+	#[test]
+	fn entities() {
+		assert_eq!(text("a&amp;b &lt;c&gt; &quot;d&quot; &apos;e&#39; &#x263A;&#X263a; x&nbsp;y &unknown; &#xFFFFFFFF;"),
+			"a&b <c> \"d\" 'e' ☺☺ x y &unknown; &#xFFFFFFFF;");
+		assert_eq!(text("<p>a <b>bold</b>\n\tword</p>"), "a bold word");
+	}
+
+	#[test]
+	fn excerpt() {
+		let long = "word ".repeat(30);
+		let html = format!("<nav><p>Home</p></nav><p class=\"x\">{0}</p><p>{0}</p><pre>not a paragraph</pre>", long);
+		assert_eq!(PageInfo::parse(&html).excerpt, Some(format!("{0} {0}", long.trim())));
+
+		let html = format!("<p>{}</p>", "ä".repeat(EXCERPT_LENGTH + 10));
+		assert_eq!(PageInfo::parse(&html).excerpt, Some("ä".repeat(EXCERPT_LENGTH) + "…"));
+		assert_eq!(PageInfo::parse("<p>too short</p>").excerpt, None);
+	}
+
+	#[test]
+	fn html() {
+		let info = PageInfo {
+			title: Some("title".to_owned()),
+			description: Some("a < b".to_owned()),
+			site_name: Some("Site".to_owned()),
+			excerpt: Some("text".to_owned()),
+		};
+		assert_eq!(info.to_html("https://example.com/?a&b"),
+			"<p><a href=\"https://example.com/?a&amp;b\">https://example.com/?a&amp;b</a> (Site)</p><p>a &lt; b</p><blockquote>text</blockquote>");
+		assert_eq!(PageInfo::default().to_html("https://a.b"), "<p><a href=\"https://a.b\">https://a.b</a></p>");
+	}
+
+	/// Answers one request with `head` and `body`, then waits `delay` before closing the connection.
+	async fn serve(head: &'static str, body: Vec<u8>, delay: Duration) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}/page", listener.local_addr().unwrap());
+		tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut request = [0; 1024];
+			let _ = socket.read(&mut request).await;
+			let _ = socket.write_all(head.as_bytes()).await;
+			// the client stops reading large pages
+			let _ = socket.write_all(&body).await;
+			tokio::time::sleep(delay).await;
+		});
+		url
+	}
+
+	#[tokio::test]
+	async fn fetch_html() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nConnection: close\r\n\r\n", b"<title>Stub</title>".to_vec(), Duration::ZERO).await;
+		let info = fetch(&Client::new(), &url).await.unwrap();
+		assert_eq!(info.title.as_deref(), Some("Stub"));
+	}
+
+	#[tokio::test]
+	async fn fetch_size_limit() {
+		let mut body = b"<title>Large</title>".to_vec();
+		body.resize(MAX_PAGE_SIZE, b' ');
+		body.extend_from_slice(br#"<meta property="og:site_name" content="after the limit">"#);
+		body.resize(2 * MAX_PAGE_SIZE, b' ');
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n", body, Duration::ZERO).await;
+		let info = fetch(&Client::new(), &url).await.unwrap();
+		assert_eq!(info.title.as_deref(), Some("Large"));
+		assert_eq!(info.site_name, None);
+	}
+
+	#[tokio::test]
+	async fn fetch_not_html() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Type: application/pdf\r\nConnection: close\r\n\r\n", b"<title>PDF</title>".to_vec(), Duration::ZERO).await;
+		assert_eq!(fetch(&Client::new(), &url).await.unwrap(), PageInfo::default());
+		let url = serve("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n", b"<title>Unknown</title>".to_vec(), Duration::ZERO).await;
+		assert_eq!(fetch(&Client::new(), &url).await.unwrap(), PageInfo::default());
+	}
+
+	#[tokio::test]
+	async fn fetch_errors() {
+		let url = serve("HTTP/1.1 404 Not Found\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n", b"<title>Missing</title>".to_vec(), Duration::ZERO).await;
+		assert!(fetch(&Client::new(), &url).await.is_err());
+		// the body never ends
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 1000\r\n\r\n", b"<title>Slow</title>".to_vec(), Duration::from_secs(5)).await;
+		let start = std::time::Instant::now();
+		assert!(fetch_with_timeout(&Client::new(), &url, Duration::from_millis(200)).await.is_err());
+		assert!(start.elapsed() < Duration::from_secs(2));
+	}
+}
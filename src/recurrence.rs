@@ -0,0 +1,352 @@
+//! Recurring reminders, stored as an RRULE-like `#recurrence` label on the reminder note.
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Weekday};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{error, local_time, parse_date, parse_weekday, Error, TimeParser, DEFAULT_HOUR};
+
+static DAY_OF_MONTH_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"^(?P<day>\d{1,2})(?:st|nd|rd|th|\.)?$").unwrap()
+});
+
+static UNTIL_REGEX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"(?i)(?:^|\s+)(?:until|bis)\s+(?P<date>\S+)\s*$").unwrap()
+});
+
+const WEEKDAYS: [Weekday; 5] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+const WEEKEND: [Weekday; 2] = [Weekday::Sat, Weekday::Sun];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frequency {
+	Daily,
+	/// On these days of every week.
+	Weekly(Vec<Weekday>),
+	/// On this day of every month. Months without this day are skipped.
+	Monthly(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recurrence {
+	pub frequency: Frequency,
+	pub hour: u32,
+	pub minute: u32,
+	/// Last day with an occurrence.
+	pub until: Option<NaiveDate>,
+}
+
+impl Recurrence {
+	/// First occurrence at or after `after`.
+	pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+		let mut date = after.date().naive_local();
+		// every rule has an occurrence within a year
+		for _ in 0..=366 {
+			if self.until.is_some_and(|until| date > until) {
+				return None;
+			}
+			if self.matches(date) {
+				// a time skipped by a DST change happens an hour later
+				let time = local_time(date, self.hour, self.minute)
+					.or_else(|| Local.from_local_datetime(&(date.and_hms_opt(self.hour, self.minute, 0)? + Duration::hours(1))).earliest());
+				if let Some(time) = time.filter(|x| *x >= after) {
+					return Some(time);
+				}
+			}
+			date = date.succ_opt()?;
+		}
+		None
+	}
+
+	fn matches(&self, date: NaiveDate) -> bool {
+		match &self.frequency {
+			Frequency::Daily => true,
+			Frequency::Weekly(days) => days.contains(&date.weekday()),
+			Frequency::Monthly(day) => date.day() == *day,
+		}
+	}
+
+	/// Description for messages, e.g. `every weekday at 08:00`.
+	pub fn describe(&self) -> String {
+		let mut text = match &self.frequency {
+			Frequency::Daily => "every day".to_owned(),
+			Frequency::Weekly(days) if days[..] == WEEKDAYS => "every weekday".to_owned(),
+			Frequency::Weekly(days) => format!("every {}", days.iter().map(|x| weekday_name(*x)).collect::<Vec<_>>().join(", ")),
+			Frequency::Monthly(day) => format!("every month on the {}{}", day, ordinal_suffix(*day)),
+		};
+		text += &format!(" at {:02}:{:02}", self.hour, self.minute);
+		if let Some(until) = self.until {
+			text += &format!(" until {}", until.format("%Y-%m-%d"));
+		}
+		text
+	}
+}
+
+/// Written like an RRULE, e.g. `FREQ=WEEKLY;BYDAY=MO,FR;BYHOUR=8;BYMINUTE=0;UNTIL=20211231`.
+impl fmt::Display for Recurrence {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.frequency {
+			Frequency::Daily => write!(f, "FREQ=DAILY")?,
+			Frequency::Weekly(days) => write!(f, "FREQ=WEEKLY;BYDAY={}", days.iter().map(|x| rrule_weekday(*x)).collect::<Vec<_>>().join(","))?,
+			Frequency::Monthly(day) => write!(f, "FREQ=MONTHLY;BYMONTHDAY={}", day)?,
+		}
+		write!(f, ";BYHOUR={};BYMINUTE={}", self.hour, self.minute)?;
+		if let Some(until) = self.until {
+			write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+		}
+		Ok(())
+	}
+}
+
+impl FromStr for Recurrence {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		let mut freq = None;
+		let mut days = Vec::new();
+		let mut day_of_month = None;
+		let mut hour = DEFAULT_HOUR;
+		let mut minute = 0;
+		let mut until = None;
+		for part in s.split(';').filter(|x| !x.is_empty()) {
+			let (key, value) = part.split_once('=').ok_or_else(|| error(format!("invalid recurrence part {:?}", part)))?;
+			match key {
+				"FREQ" => freq = Some(value.to_owned()),
+				"BYDAY" => for day in value.split(',') {
+					days.push(WEEKDAYS.iter().chain(&WEEKEND).copied()
+						.find(|x| rrule_weekday(*x) == day)
+						.ok_or_else(|| error(format!("invalid weekday {:?}", day)))?);
+				},
+				"BYMONTHDAY" => day_of_month = Some(value.parse()?),
+				"BYHOUR" => hour = value.parse()?,
+				"BYMINUTE" => minute = value.parse()?,
+				"UNTIL" => until = Some(NaiveDate::parse_from_str(value, "%Y%m%d")?),
+				_ => return Err(error(format!("unknown recurrence part {:?}", key))),
+			}
+		}
+		let frequency = match freq.as_deref() {
+			Some("DAILY") => Frequency::Daily,
+			Some("WEEKLY") if !days.is_empty() => Frequency::Weekly(days),
+			Some("MONTHLY") => Frequency::Monthly(day_of_month.filter(|x| (1..=31).contains(x)).ok_or_else(|| error("BYMONTHDAY missing or invalid"))?),
+			_ => return Err(error(format!("unsupported recurrence {:?}", s))),
+		};
+		if hour >= 24 || minute >= 60 {
+			return Err(error(format!("invalid time in recurrence {:?}", s)));
+		}
+		Ok(Recurrence { frequency, hour, minute, until })
+	}
+}
+
+/// Parse the schedule of `/every` at the start of `s` and return it with the remaining text.
+///
+/// Understands `day`, `weekday`, `weekend`, weekday names (`monday, thursday`), `week on friday`
+/// and `month on the 1st` (or German equivalents), followed by an optional time (default 09:00).
+/// An `until <date>` at the end of the text sets the last day, other words after `until` are part of the text.
+pub fn parse_every(s: &str, today: NaiveDate) -> Option<(Recurrence, &str)> {
+	let mut parser = TimeParser::new(s);
+	let word = parser.next()?;
+	let frequency = match &*word {
+		"day" | "daily" | "tag" | "täglich" => Frequency::Daily,
+		"weekday" | "weekdays" | "werktag" | "werktags" => Frequency::Weekly(WEEKDAYS.to_vec()),
+		"weekend" | "wochenende" => Frequency::Weekly(WEEKEND.to_vec()),
+		"week" | "woche" => {
+			parser.eat(&["on", "am"]);
+			let start = parser.pos;
+			match parser.next().and_then(|x| parse_weekday(&x)) {
+				Some(day) => Frequency::Weekly(vec![day]),
+				None => {
+					parser.pos = start;
+					Frequency::Weekly(vec![today.weekday()])
+				}
+			}
+		},
+		"month" | "monat" => {
+			parser.eat(&["on", "am"]);
+			parser.eat(&["the", "den"]);
+			let start = parser.pos;
+			match parser.next().and_then(|x| DAY_OF_MONTH_REGEX.captures(&x).and_then(|x| x["day"].parse().ok())) {
+				Some(day) if (1..=31).contains(&day) => Frequency::Monthly(day),
+				_ => {
+					parser.pos = start;
+					Frequency::Monthly(today.day())
+				}
+			}
+		},
+		word => {
+			let mut days = vec![parse_weekday(word)?];
+			loop {
+				let start = parser.pos;
+				parser.eat(&["and", "und"]);
+				match parser.next().and_then(|x| parse_weekday(&x)) {
+					Some(day) => days.push(day),
+					None => {
+						parser.pos = start;
+						break;
+					}
+				}
+			}
+			days.sort_by_key(|x| x.num_days_from_monday());
+			days.dedup();
+			Frequency::Weekly(days)
+		},
+	};
+	let (hour, minute) = parser.time().unwrap_or((DEFAULT_HOUR, 0));
+	let mut text = parser.rest(s);
+	let mut until = None;
+	if let Some(data) = UNTIL_REGEX.captures(text) {
+		if let Some(date) = parse_date(&data["date"].to_lowercase(), today) {
+			until = Some(date);
+			text = &text[..data.get(0).unwrap().start()];
+		}
+	}
+	Some((Recurrence { frequency, hour, minute, until }, text.trim()))
+}
+
+fn rrule_weekday(day: Weekday) -> &'static str {
+	match day {
+		Weekday::Mon => "MO",
+		Weekday::Tue => "TU",
+		Weekday::Wed => "WE",
+		Weekday::Thu => "TH",
+		Weekday::Fri => "FR",
+		Weekday::Sat => "SA",
+		Weekday::Sun => "SU",
+	}
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+	match day {
+		Weekday::Mon => "Monday",
+		Weekday::Tue => "Tuesday",
+		Weekday::Wed => "Wednesday",
+		Weekday::Thu => "Thursday",
+		Weekday::Fri => "Friday",
+		Weekday::Sat => "Saturday",
+		Weekday::Sun => "Sunday",
+	}
+}
+
+fn ordinal_suffix(day: u32) -> &'static str {
+	match day {
+		1 | 21 | 31 => "st",
+		2 | 22 => "nd",
+		3 | 23 => "rd",
+		_ => "th",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn every(s: &str) -> Option<(Recurrence, &str)> {
+		// a Wednesday
+		parse_every(s, NaiveDate::from_ymd(2021, 5, 5))
+	}
+
+	fn recurrence(frequency: Frequency, hour: u32, minute: u32, until: Option<NaiveDate>) -> Recurrence {
+		Recurrence { frequency, hour, minute, until }
+	}
+
+	#[test]
+	fn rrule_round_trip() {
+		let cases = [
+			(recurrence(Frequency::Daily, 9, 0, None), "FREQ=DAILY;BYHOUR=9;BYMINUTE=0"),
+			(recurrence(Frequency::Weekly(vec![Weekday::Mon, Weekday::Fri]), 8, 30, None), "FREQ=WEEKLY;BYDAY=MO,FR;BYHOUR=8;BYMINUTE=30"),
+			(recurrence(Frequency::Monthly(31), 18, 5, NaiveDate::from_ymd_opt(2021, 12, 31)), "FREQ=MONTHLY;BYMONTHDAY=31;BYHOUR=18;BYMINUTE=5;UNTIL=20211231"),
+		];
+		for (recurrence, rrule) in cases {
+			assert_eq!(recurrence.to_string(), rrule);
+			assert_eq!(rrule.parse::<Recurrence>().unwrap(), recurrence);
+		}
+	}
+
+	#[test]
+	fn invalid_rrule() {
+		for rrule in ["FREQ=YEARLY", "FREQ=WEEKLY", "FREQ=MONTHLY;BYMONTHDAY=32", "FREQ=DAILY;BYHOUR=24", "FREQ=DAILY;BYDAY=XX", "FREQ=DAILY;COUNT=3", "FREQ"] {
+			assert!(rrule.parse::<Recurrence>().is_err(), "{:?}", rrule);
+		}
+	}
+
+	#[test]
+	fn next_after() {
+		let weekly = recurrence(Frequency::Weekly(vec![Weekday::Mon, Weekday::Fri]), 8, 0, None);
+		let after = Local.ymd(2021, 5, 5).and_hms(12, 0, 0);
+		assert_eq!(weekly.next_after(after), Some(Local.ymd(2021, 5, 7).and_hms(8, 0, 0)));
+		// an occurrence at exactly `after` counts
+		assert_eq!(weekly.next_after(Local.ymd(2021, 5, 7).and_hms(8, 0, 0)), Some(Local.ymd(2021, 5, 7).and_hms(8, 0, 0)));
+
+		// months without a 31st are skipped
+		let monthly = recurrence(Frequency::Monthly(31), 9, 0, None);
+		assert_eq!(monthly.next_after(Local.ymd(2021, 1, 31).and_hms(10, 0, 0)), Some(Local.ymd(2021, 3, 31).and_hms(9, 0, 0)));
+		assert_eq!(monthly.next_after(Local.ymd(2021, 4, 1).and_hms(0, 0, 0)), Some(Local.ymd(2021, 5, 31).and_hms(9, 0, 0)));
+
+		let until = recurrence(Frequency::Daily, 9, 0, NaiveDate::from_ymd_opt(2021, 5, 6));
+		assert_eq!(until.next_after(Local.ymd(2021, 5, 6).and_hms(8, 0, 0)), Some(Local.ymd(2021, 5, 6).and_hms(9, 0, 0)));
+		assert_eq!(until.next_after(Local.ymd(2021, 5, 6).and_hms(10, 0, 0)), None);
+	}
+
+	#[test]
+	fn next_after_dst() {
+		// 02:30 does not exist on the day clocks are set forward in many time zones,
+		// it still has an occurrence on each day around both DST changes of Europe and the US
+		let daily = recurrence(Frequency::Daily, 2, 30, None);
+		for (month, day) in [(3, 14), (3, 28), (10, 31), (11, 7)] {
+			let date = NaiveDate::from_ymd(2021, month, day);
+			let mut after = Local.from_local_date(&date.pred()).unwrap().and_hms(12, 0, 0);
+			for expected in [date, date.succ()] {
+				let time = daily.next_after(after).unwrap();
+				assert_eq!(time.date().naive_local(), expected);
+				after = time + Duration::minutes(1);
+			}
+		}
+	}
+
+	#[test]
+	fn schedules() {
+		let cases = [
+			("day 08:00 standup", Frequency::Daily, 8, 0, "standup"),
+			("täglich um 7 Uhr Tabletten", Frequency::Daily, 7, 0, "tabletten"),
+			("weekday standup", Frequency::Weekly(WEEKDAYS.to_vec()), 9, 0, "standup"),
+			("weekend at 10am sleep in", Frequency::Weekly(WEEKEND.to_vec()), 10, 0, "sleep in"),
+			("week water plants", Frequency::Weekly(vec![Weekday::Wed]), 9, 0, "water plants"),
+			("week on friday 17:30 review", Frequency::Weekly(vec![Weekday::Fri]), 17, 30, "review"),
+			("friday, monday and tuesday 8:15 gym", Frequency::Weekly(vec![Weekday::Mon, Weekday::Tue, Weekday::Fri]), 8, 15, "gym"),
+			("month on the 1st rent", Frequency::Monthly(1), 9, 0, "rent"),
+			("monat am 15. 12:00 Miete", Frequency::Monthly(15), 12, 0, "miete"),
+			("month bills", Frequency::Monthly(5), 9, 0, "bills"),
+		];
+		for (input, frequency, hour, minute, text) in cases {
+			let (recurrence, rest) = every(input).unwrap_or_else(|| panic!("{:?} not parsed", input));
+			assert_eq!(recurrence, Recurrence { frequency, hour, minute, until: None }, "{:?}", input);
+			assert_eq!(rest.to_lowercase(), text, "{:?}", input);
+		}
+		assert_eq!(every("sometimes tea"), None);
+	}
+
+	#[test]
+	fn schedule_until() {
+		let (recurrence, text) = every("day 08:00 stretch until 2021-06-30").unwrap();
+		assert_eq!(recurrence.until, NaiveDate::from_ymd_opt(2021, 6, 30));
+		assert_eq!(text, "stretch");
+		let (recurrence, text) = every("weekday Gießen bis 24.12.").unwrap();
+		assert_eq!(recurrence.until, NaiveDate::from_ymd_opt(2021, 12, 24));
+		assert_eq!(text, "Gießen");
+		// not a date, so part of the text
+		let (recurrence, text) = every("day 08:00 wait until lunch").unwrap();
+		assert_eq!(recurrence.until, None);
+		assert_eq!(text, "wait until lunch");
+	}
+
+	#[test]
+	fn description() {
+		let (recurrence, _) = every("weekday 8:00 standup until 2021-06-30").unwrap();
+		assert_eq!(recurrence.describe(), "every weekday at 08:00 until 2021-06-30");
+		let (recurrence, _) = every("monday and friday gym").unwrap();
+		assert_eq!(recurrence.describe(), "every Monday, Friday at 09:00");
+		let (recurrence, _) = every("month on the 22nd rent").unwrap();
+		assert_eq!(recurrence.describe(), "every month on the 22nd at 09:00");
+	}
+}
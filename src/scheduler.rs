@@ -0,0 +1,300 @@
+//! Computing which alerts to send for tasks, reminders and events.
+
+use chrono::{DateTime, Duration, Local};
+use log::warn;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::backend::{Event, Task};
+use crate::state::SentAlert;
+use crate::trilium::Note;
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+	Task,
+	Reminder,
+	Event,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+	pub kind: AlertKind,
+	pub note_id: String,
+	pub title: String,
+	/// Time of the task or event.
+	pub due: DateTime<Local>,
+	/// How long before `due` the alert is sent.
+	pub lead: Duration,
+	/// Whether the alert is for an occurrence of a recurring or daily reminder, which can only be snoozed.
+	pub recurring: bool,
+}
+
+impl Alert {
+	/// When the alert should be delivered.
+	pub fn time(&self) -> DateTime<Local> {
+		self.due - self.lead
+	}
+
+	/// Whether the alert is delivered more than `grace` after its time, e.g. after a restart.
+	pub fn is_late(&self, now: DateTime<Local>, grace: Duration) -> bool {
+		now - self.time() > grace
+	}
+
+	pub fn record(&self) -> SentAlert {
+		SentAlert {
+			note_id: self.note_id.clone(),
+			due: self.due,
+			lead_minutes: self.lead.num_minutes(),
+		}
+	}
+}
+
+/// Orders alerts by the time they are due.
+#[derive(Debug)]
+struct QueuedAlert(Alert);
+
+impl PartialEq for QueuedAlert {
+	fn eq(&self, other: &Self) -> bool {
+		self.0.time() == other.0.time()
+	}
+}
+
+impl Eq for QueuedAlert {}
+
+impl PartialOrd for QueuedAlert {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for QueuedAlert {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.0.time().cmp(&other.0.time())
+	}
+}
+
+/// Upcoming alerts, earliest first.
+#[derive(Debug, Default)]
+pub struct AlertQueue {
+	heap: BinaryHeap<Reverse<QueuedAlert>>,
+}
+
+impl AlertQueue {
+	/// Queue all alerts of the tasks and events that are due after `since`.
+	///
+	/// Reminders are alerted at their time, tasks and events before their time using
+	/// the lead times (see [`note_lead_times`]).
+	pub fn new(tasks: &[Task], events: &[Event], lead_times: &[Duration], since: DateTime<Local>) -> Self {
+		let mut queue = AlertQueue::default();
+		for task in tasks {
+			let (kind, leads) = if task.is_reminder {
+				let leads = if alerts_disabled(&task.note) { Vec::new() } else { vec![Duration::zero()] };
+				(AlertKind::Reminder, leads)
+			} else {
+				(AlertKind::Task, note_lead_times(&task.note, lead_times))
+			};
+			for lead in leads {
+				queue.push(Alert {
+					kind,
+					note_id: task.note.note_id.clone(),
+					title: task.note.title.clone(),
+					due: task.todo_time,
+					lead,
+					recurring: task.repeats(),
+				});
+			}
+		}
+		for event in events {
+			for lead in note_lead_times(&event.note, lead_times) {
+				queue.push(Alert {
+					kind: AlertKind::Event,
+					note_id: event.note.note_id.clone(),
+					title: event.note.title.clone(),
+					due: event.todo_time,
+					lead,
+					recurring: false,
+				});
+			}
+		}
+		queue.heap.retain(|x| (x.0).0.time() > since);
+		queue
+	}
+
+	pub fn push(&mut self, alert: Alert) {
+		self.heap.push(Reverse(QueuedAlert(alert)));
+	}
+
+	/// Time of the earliest alert.
+	pub fn next_time(&self) -> Option<DateTime<Local>> {
+		self.heap.peek().map(|x| (x.0).0.time())
+	}
+
+	/// Remove the earliest alert if it is due at `now`.
+	pub fn pop_due(&mut self, now: DateTime<Local>) -> Option<Alert> {
+		if self.next_time()? <= now {
+			self.heap.pop().map(|x| (x.0).0)
+		} else {
+			None
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.heap.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.heap.is_empty()
+	}
+}
+
+/// Whether the note has a `#noAlert` label.
+fn alerts_disabled(note: &Note) -> bool {
+	note.label("noAlert").is_some_and(|x| x != "false")
+}
+
+/// Lead times of a task or event note: the values of its `#alertBefore` label
+/// (e.g. `#alertBefore=2h,15m`) or the configured default.
+/// Notes with a `#noAlert` label get no alerts at all.
+pub fn note_lead_times(note: &Note, default: &[Duration]) -> Vec<Duration> {
+	if alerts_disabled(note) {
+		return Vec::new();
+	}
+	match note.label("alertBefore") {
+		Some(value) => match parse_lead_times(value) {
+			Ok(lead_times) => lead_times,
+			Err(e) => {
+				warn!("note {}: invalid alertBefore {:?}: {}", note.note_id, value, e);
+				default.to_vec()
+			}
+		},
+		None => default.to_vec(),
+	}
+}
+
+/// Parse a comma-separated list of durations, e.g. `2h,15m`.
+pub fn parse_lead_times(s: &str) -> Result<Vec<Duration>, Error> {
+	s.split(',')
+		.map(str::trim)
+		.filter(|x| !x.is_empty())
+		.map(crate::parse_duration)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::TimeZone;
+
+	use crate::trilium::Attribute;
+
+	fn note(id: &str, labels: &[(&str, &str)]) -> Note {
+		let mut note = Note {
+			note_id: id.to_owned(),
+			title: id.to_owned(),
+			..Note::default()
+		};
+		for (name, value) in labels {
+			note.attributes.push(Attribute::label(id, name, value));
+		}
+		note
+	}
+
+	fn task(id: &str, due: DateTime<Local>, is_reminder: bool) -> Task {
+		Task { note: note(id, &[]), todo_time: due, is_reminder, recurrence: None, daily: false }
+	}
+
+	fn due() -> DateTime<Local> {
+		Local.ymd(2021, 5, 10).and_hms(12, 0, 0)
+	}
+
+	fn lead_times() -> Vec<Duration> {
+		vec![Duration::hours(48), Duration::hours(24), Duration::hours(1), Duration::minutes(10)]
+	}
+
+	#[test]
+	fn downtime() {
+		// the bot was offline from before the 48h alert until after the 1h alert
+		let since = due() - Duration::days(3);
+		let mut queue = AlertQueue::new(&[task("a", due(), false)], &[], &lead_times(), since);
+		assert_eq!(queue.len(), 4);
+		let now = due() - Duration::minutes(30);
+		let mut leads = Vec::new();
+		while let Some(alert) = queue.pop_due(now) {
+			assert!(alert.is_late(now, Duration::minutes(5)));
+			leads.push(alert.lead);
+		}
+		assert_eq!(leads, vec![Duration::hours(48), Duration::hours(24), Duration::hours(1)]);
+		assert_eq!(queue.next_time(), Some(due() - Duration::minutes(10)));
+	}
+
+	#[test]
+	fn since_is_exclusive() {
+		// the alert at `since` was already checked
+		let since = due() - Duration::hours(1);
+		let queue = AlertQueue::new(&[task("a", due(), false), task("b", since, true)], &[], &lead_times(), since);
+		assert_eq!(queue.len(), 1);
+		assert_eq!(queue.next_time(), Some(due() - Duration::minutes(10)));
+	}
+
+	#[test]
+	fn order() {
+		let tasks = [task("later", due() + Duration::minutes(30), true), task("task", due(), false)];
+		let events = [Event { note: note("event", &[]), todo_time: due() - Duration::minutes(5) }];
+		let mut queue = AlertQueue::new(&tasks, &events, &lead_times(), due() - Duration::minutes(30));
+		let mut alerts = Vec::new();
+		while let Some(alert) = queue.pop_due(due() + Duration::hours(1)) {
+			alerts.push((alert.kind, alert.time(), alert.note_id));
+		}
+		assert_eq!(alerts, vec![
+			(AlertKind::Event, due() - Duration::minutes(15), "event".to_owned()),
+			(AlertKind::Task, due() - Duration::minutes(10), "task".to_owned()),
+			(AlertKind::Reminder, due() + Duration::minutes(30), "later".to_owned()),
+		]);
+		assert!(queue.is_empty());
+	}
+
+	#[test]
+	fn not_due_yet() {
+		let mut queue = AlertQueue::new(&[task("a", due(), true)], &[], &lead_times(), due() - Duration::days(1));
+		assert_eq!(queue.pop_due(due() - Duration::seconds(1)), None);
+		let alert = queue.pop_due(due()).unwrap();
+		assert_eq!(alert.lead, Duration::zero());
+		assert!(!alert.is_late(due(), Duration::minutes(5)));
+		assert_eq!(alert.record(), SentAlert { note_id: "a".to_owned(), due: due(), lead_minutes: 0 });
+	}
+
+	#[test]
+	fn lead_times_of_note() {
+		let default = lead_times();
+		assert_eq!(note_lead_times(&note("a", &[]), &default), default);
+		assert_eq!(note_lead_times(&note("a", &[("alertBefore", "2h,15m")]), &default), vec![Duration::hours(2), Duration::minutes(15)]);
+		assert_eq!(note_lead_times(&note("a", &[("alertBefore", "2h, soon")]), &default), default);
+		assert_eq!(note_lead_times(&note("a", &[("noAlert", "")]), &default), Vec::new());
+		assert_eq!(note_lead_times(&note("a", &[("noAlert", "false")]), &default), default);
+		assert_eq!(note_lead_times(&note("a", &[("noAlert", "true"), ("alertBefore", "1h")]), &default), Vec::new());
+	}
+
+	#[test]
+	fn lead_times_parsing() {
+		assert_eq!(parse_lead_times("2h,15m").unwrap(), vec![Duration::hours(2), Duration::minutes(15)]);
+		assert_eq!(parse_lead_times(" 1d , 1h30m ,").unwrap(), vec![Duration::days(1), Duration::minutes(90)]);
+		assert_eq!(parse_lead_times("").unwrap(), Vec::new());
+		assert!(parse_lead_times("2h,x").is_err());
+		assert!(parse_lead_times("2 hours").is_err());
+	}
+
+	#[test]
+	fn disabled_alerts() {
+		assert!(!alerts_disabled(&note("a", &[])));
+		assert!(alerts_disabled(&note("a", &[("noAlert", "")])));
+		assert!(alerts_disabled(&note("a", &[("noAlert", "true")])));
+		assert!(!alerts_disabled(&note("a", &[("noAlert", "false")])));
+		// reminders with #noAlert are not queued either
+		let mut reminder = task("a", due(), true);
+		reminder.note = note("a", &[("noAlert", "")]);
+		assert!(AlertQueue::new(&[reminder], &[], &lead_times(), due() - Duration::days(1)).is_empty());
+	}
+}
@@ -0,0 +1,147 @@
+//! Origin of forwarded messages.
+
+use chrono::{DateTime, Local, TimeZone};
+use telegram_bot::{Forward, ForwardFrom, User};
+
+use crate::escape_html;
+
+/// Where a forwarded message was originally posted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+	/// Name of the user or channel.
+	pub name: String,
+	/// Link to the channel post or user.
+	pub url: Option<String>,
+	/// Time of the original message.
+	pub date: DateTime<Local>,
+	/// Identifies the user or channel, for grouping notes by their source.
+	pub key: String,
+}
+
+impl Source {
+	pub fn new(forward: &Forward) -> Source {
+		let date = Local.timestamp_opt(forward.date, 0).single().unwrap_or_else(Local::now);
+		match &forward.from {
+			ForwardFrom::User { user } => Source {
+				name: user_name(user),
+				url: user.username.as_ref().map(|x| format!("https://t.me/{}", x)),
+				date,
+				key: format!("user{}", user.id),
+			},
+			ForwardFrom::Channel { channel, message_id } => {
+				let url = match &channel.username {
+					Some(username) => format!("https://t.me/{}/{}", username, message_id),
+					None => {
+						// links to private channels use the ID without the -100 prefix
+						let id = channel.id.to_string();
+						format!("https://t.me/c/{}/{}", id.strip_prefix("-100").unwrap_or(&id), message_id)
+					},
+				};
+				Source {
+					name: channel.title.clone(),
+					url: Some(url),
+					date,
+					key: format!("channel{}", channel.id),
+				}
+			},
+			ForwardFrom::ChannelHiddenUser { sender_name } => Source {
+				name: sender_name.clone(),
+				url: None,
+				date,
+				// the key is used in a search query
+				key: format!("hidden:{}", sender_name.replace('"', "")),
+			},
+		}
+	}
+
+	/// Labels of the note: `#source`, `#sourceUrl` and `#originalDate`.
+	pub fn labels(&self) -> Vec<(&'static str, String)> {
+		let mut labels = vec![("source", self.name.clone())];
+		if let Some(url) = &self.url {
+			labels.push(("sourceUrl", url.clone()));
+		}
+		labels.push(("originalDate", self.date.format("%Y-%m-%dT%H:%M:%S").to_string()));
+		labels
+	}
+
+	/// First line of the note content.
+	pub fn header_html(&self) -> String {
+		let name = match &self.url {
+			Some(url) => format!("<a href=\"{}\">{}</a>", escape_html(url), escape_html(&self.name)),
+			None => escape_html(&self.name),
+		};
+		format!("<p><i>Forwarded from {}, {}</i></p>", name, self.date.format("%Y-%m-%d %H:%M"))
+	}
+}
+
+fn user_name(user: &User) -> String {
+	let mut name = user.first_name.clone();
+	if let Some(last_name) = &user.last_name {
+		name += " ";
+		name += last_name;
+	}
+	name
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use telegram_bot::{Channel, ChannelId, UserId};
+
+	fn user(username: Option<&str>) -> User {
+		User {
+			id: UserId::new(42),
+			first_name: "Ada".to_owned(),
+			last_name: Some("Lovelace".to_owned()),
+			username: username.map(ToOwned::to_owned),
+			is_bot: false,
+			language_code: None,
+		}
+	}
+
+	fn channel(username: Option<&str>) -> ForwardFrom {
+		ForwardFrom::Channel {
+			channel: Channel {
+				id: ChannelId::new(-1001234567890),
+				title: "News & more".to_owned(),
+				username: username.map(ToOwned::to_owned),
+				invite_link: None,
+			},
+			message_id: 7,
+		}
+	}
+
+	fn source(from: ForwardFrom) -> Source {
+		Source::new(&Forward { date: 1620230400, from })
+	}
+
+	#[test]
+	fn user_source() {
+		let named = source(ForwardFrom::User { user: user(Some("ada")) });
+		assert_eq!((&*named.name, named.url.as_deref(), &*named.key), ("Ada Lovelace", Some("https://t.me/ada"), "user42"));
+		assert_eq!(named.date, Local.timestamp(1620230400, 0));
+		assert_eq!(source(ForwardFrom::User { user: user(None) }).url, None);
+	}
+
+	#[test]
+	fn hidden_user_source() {
+		let source = source(ForwardFrom::ChannelHiddenUser { sender_name: "Ada \"the first\"".to_owned() });
+		assert_eq!((&*source.name, source.url.as_deref(), &*source.key), ("Ada \"the first\"", None, "hidden:Ada the first"));
+		assert_eq!(source.header_html(), format!("<p><i>Forwarded from Ada &quot;the first&quot;, {}</i></p>", source.date.format("%Y-%m-%d %H:%M")));
+	}
+
+	#[test]
+	fn channel_source() {
+		let public = source(channel(Some("news")));
+		assert_eq!((&*public.name, public.url.as_deref(), &*public.key), ("News & more", Some("https://t.me/news/7"), "channel-1001234567890"));
+		let private = source(channel(None));
+		assert_eq!(private.url.as_deref(), Some("https://t.me/c/1234567890/7"));
+		assert_eq!(private.labels(), vec![
+			("source", "News & more".to_owned()),
+			("sourceUrl", "https://t.me/c/1234567890/7".to_owned()),
+			("originalDate", private.date.format("%Y-%m-%dT%H:%M:%S").to_string()),
+		]);
+		assert!(private.header_html().starts_with("<p><i>Forwarded from <a href=\"https://t.me/c/1234567890/7\">News &amp; more</a>, "));
+	}
+}
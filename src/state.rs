@@ -0,0 +1,212 @@
+//! Bot state that survives restarts, saved as JSON in the data directory.
+
+use chrono::{DateTime, Duration, Local, NaiveDate};
+use serde_derive::{Deserialize, Serialize};
+use telegram_bot::MessageId;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::Error;
+
+/// A reminder that is being created using the inline keyboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReminderDraft {
+	/// Message with the inline keyboard.
+	pub message_id: MessageId,
+	pub text: String,
+	pub start: DateTime<Local>,
+	/// Offset added to `start` using the keyboard, in minutes.
+	pub offset_minutes: i64,
+}
+
+impl ReminderDraft {
+	pub fn offset(&self) -> Duration {
+		Duration::minutes(self.offset_minutes)
+	}
+
+	pub fn time(&self) -> DateTime<Local> {
+		self.start + self.offset()
+	}
+}
+
+/// An alert that was already delivered.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SentAlert {
+	pub note_id: String,
+	/// Time of the task or event.
+	pub due: DateTime<Local>,
+	/// Lead time of the alert in minutes.
+	pub lead_minutes: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+	/// Offset for the next getUpdates request.
+	pub update_offset: i64,
+	/// Reminders being created, one per keyboard message.
+	pub drafts: Vec<ReminderDraft>,
+	pub sent_alerts: HashSet<SentAlert>,
+	/// Alerts up to this time have been sent.
+	pub last_alert_check: Option<DateTime<Local>>,
+	/// Day of the last morning digest.
+	pub last_digest: Option<NaiveDate>,
+	/// Day of the last evening review.
+	pub last_review: Option<NaiveDate>,
+}
+
+impl State {
+	/// The draft of the keyboard message.
+	pub fn draft(&self, message_id: MessageId) -> Option<&ReminderDraft> {
+		self.drafts.iter().find(|x| x.message_id == message_id)
+	}
+
+	/// Add a draft or replace the draft with the same keyboard message.
+	pub fn set_draft(&mut self, draft: ReminderDraft) {
+		self.remove_draft(draft.message_id);
+		self.drafts.push(draft);
+	}
+
+	pub fn remove_draft(&mut self, message_id: MessageId) {
+		self.drafts.retain(|x| x.message_id != message_id);
+	}
+}
+
+pub struct StateStore {
+	/// `None` for stores that are not persisted.
+	path: Option<PathBuf>,
+	state: Mutex<State>,
+}
+
+impl StateStore {
+	/// Load the state file in the data directory, or start with an empty state if it does not exist yet.
+	pub fn open(data_dir: &Path) -> Result<StateStore, Error> {
+		fs::create_dir_all(data_dir)?;
+		let path = data_dir.join("state.json");
+		let state = if path.exists() {
+			serde_json::from_str(&fs::read_to_string(&path)?)?
+		} else {
+			State::default()
+		};
+		Ok(StateStore {
+			path: Some(path),
+			state: Mutex::new(state),
+		})
+	}
+
+	/// State that is only kept in memory, for tests.
+	pub fn in_memory() -> StateStore {
+		StateStore {
+			path: None,
+			state: Mutex::new(State::default()),
+		}
+	}
+
+	pub fn read<T>(&self, f: impl FnOnce(&State) -> T) -> T {
+		f(&self.state.lock().unwrap())
+	}
+
+	/// Modify the state and save it to disk.
+	pub fn update<T>(&self, f: impl FnOnce(&mut State) -> T) -> Result<T, Error> {
+		let mut state = self.state.lock().unwrap();
+		let result = f(&mut state);
+		// alerts are not sent for past items, so old records can be dropped
+		let cutoff = Local::now() - Duration::days(2);
+		state.sent_alerts.retain(|x| x.due > cutoff);
+		if let Some(path) = &self.path {
+			// write to a temporary file first so a crash does not leave a truncated state file
+			let tmp = path.with_extension("json.tmp");
+			fs::write(&tmp, serde_json::to_string_pretty(&*state)?)?;
+			fs::rename(&tmp, path)?;
+		}
+		Ok(result)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::TimeZone;
+
+	/// An empty directory for the test, removed when dropped.
+	struct TempDir(PathBuf);
+
+	impl TempDir {
+		fn new(name: &str) -> TempDir {
+			let path = std::env::temp_dir().join(format!("telegram_notes_bot-{}-{}", std::process::id(), name));
+			let _ = fs::remove_dir_all(&path);
+			TempDir(path)
+		}
+	}
+
+	impl Drop for TempDir {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.0);
+		}
+	}
+
+	fn sent_alert(due: DateTime<Local>) -> SentAlert {
+		SentAlert { note_id: "abc".to_owned(), due, lead_minutes: 10 }
+	}
+
+	#[test]
+	fn reopen() {
+		let dir = TempDir::new("reopen");
+		let due = Local::now() + Duration::hours(1);
+		let draft = ReminderDraft {
+			message_id: MessageId::new(42),
+			text: "call mom".to_owned(),
+			start: Local.ymd(2021, 5, 1).and_hms(18, 0, 0),
+			offset_minutes: 70,
+		};
+		let store = StateStore::open(&dir.0).unwrap();
+		store.update(|state| {
+			state.update_offset = 7;
+			state.set_draft(draft.clone());
+			state.sent_alerts.insert(sent_alert(due));
+			state.last_alert_check = Some(due);
+			state.last_digest = Some(NaiveDate::from_ymd(2021, 5, 1));
+		}).unwrap();
+
+		let store = StateStore::open(&dir.0).unwrap();
+		store.read(|state| {
+			assert_eq!(state.update_offset, 7);
+			let reopened = state.draft(MessageId::new(42)).unwrap();
+			assert_eq!((&reopened.text, reopened.time()), (&draft.text, draft.time()));
+			assert_eq!(state.sent_alerts, [sent_alert(due)].iter().cloned().collect());
+			assert_eq!(state.last_alert_check, Some(due));
+			assert_eq!(state.last_digest, Some(NaiveDate::from_ymd(2021, 5, 1)));
+			assert_eq!(state.last_review, None);
+		});
+	}
+
+	#[test]
+	fn write_through_temporary_file() {
+		let dir = TempDir::new("write");
+		let store = StateStore::open(&dir.0).unwrap();
+		store.update(|state| state.update_offset = 1).unwrap();
+		assert!(dir.0.join("state.json").exists());
+		assert!(!dir.0.join("state.json.tmp").exists());
+
+		// a failed write leaves the previous state file intact
+		fs::create_dir(dir.0.join("state.json.tmp")).unwrap();
+		assert!(store.update(|state| state.update_offset = 2).is_err());
+		let state: State = serde_json::from_str(&fs::read_to_string(dir.0.join("state.json")).unwrap()).unwrap();
+		assert_eq!(state.update_offset, 1);
+	}
+
+	#[test]
+	fn prune_sent_alerts() {
+		let store = StateStore::in_memory();
+		let recent = sent_alert(Local::now() - Duration::days(1));
+		store.update(|state| {
+			state.sent_alerts.insert(sent_alert(Local::now() - Duration::days(3)));
+			state.sent_alerts.insert(recent.clone());
+		}).unwrap();
+		assert_eq!(store.read(|state| state.sent_alerts.clone()), [recent].iter().cloned().collect());
+	}
+}
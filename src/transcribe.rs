@@ -0,0 +1,97 @@
+//! Speech-to-text for voice messages.
+//!
+//! [`CommandTranscriber`] runs a local program, e.g. whisper.cpp, other engines can implement [`Transcriber`].
+
+use async_trait::async_trait;
+use log::warn;
+use tokio::process::Command;
+
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::{error, Error};
+
+/// Placeholder in the configured command that is replaced by the path of the audio file.
+pub const FILE_PLACEHOLDER: &str = "{file}";
+
+/// Transcriptions taking longer than this are aborted.
+const TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[async_trait]
+pub trait Transcriber: Send + Sync {
+	/// Text spoken in the audio file, `extension` is the file extension matching its format (e.g. `ogg`).
+	async fn transcribe(&self, data: &[u8], extension: &str) -> Result<String, Error>;
+}
+
+/// Runs a command that prints the transcript of an audio file to stdout,
+/// like whisper.cpp with `--no-timestamps`.
+pub struct CommandTranscriber {
+	/// Program and arguments, [`FILE_PLACEHOLDER`] is replaced by the path of the audio file.
+	command: Vec<String>,
+	/// Directory of the temporary audio files.
+	dir: PathBuf,
+}
+
+impl CommandTranscriber {
+	pub fn new(command: Vec<String>, dir: PathBuf) -> Self {
+		CommandTranscriber { command, dir }
+	}
+}
+
+#[async_trait]
+impl Transcriber for CommandTranscriber {
+	async fn transcribe(&self, data: &[u8], extension: &str) -> Result<String, Error> {
+		tokio::fs::create_dir_all(&self.dir).await?;
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+		let path = self.dir.join(format!("transcribe-{}.{}", COUNTER.fetch_add(1, Ordering::Relaxed), extension));
+		tokio::fs::write(&path, data).await?;
+		let file = path.to_string_lossy();
+		let args: Vec<_> = self.command.iter().map(|x| x.replace(FILE_PLACEHOLDER, &file)).collect();
+		let output = Command::new(&args[0])
+			.args(&args[1..])
+			.stdin(Stdio::null())
+			.kill_on_drop(true)
+			.output();
+		let output = tokio::time::timeout(TIMEOUT, output).await;
+		if let Err(e) = tokio::fs::remove_file(&path).await {
+			warn!("could not remove {}: {}", path.display(), e);
+		}
+		let output = output.map_err(|_| error("transcription timed out"))??;
+		if !output.status.success() {
+			return Err(error(format!("transcription failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+		}
+		// whisper.cpp prints one line per segment
+		let text = String::from_utf8_lossy(&output.stdout);
+		Ok(text.lines().map(str::trim).filter(|x| !x.is_empty()).collect::<Vec<_>>().join(" "))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn transcriber(name: &str, script: &str) -> CommandTranscriber {
+		let dir = std::env::temp_dir().join(format!("telegram_notes_bot-{}-{}", std::process::id(), name));
+		CommandTranscriber::new(vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()], dir)
+	}
+
+	#[tokio::test]
+	async fn transcribe() {
+		let transcriber = transcriber("transcribe", "cat {file}");
+		let text = transcriber.transcribe(b"  hello\n\nworld  \n", "ogg").await.unwrap();
+		assert_eq!(text, "hello world");
+		// the audio file is removed afterwards
+		assert_eq!(std::fs::read_dir(&transcriber.dir).unwrap().count(), 0);
+		std::fs::remove_dir(&transcriber.dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn failure() {
+		let transcriber = transcriber("failure", "echo 'no model' >&2; rm {file}; exit 1");
+		let e = transcriber.transcribe(b"audio", "ogg").await.unwrap_err();
+		assert!(e.to_string().ends_with("transcription failed (exit status: 1): no model"), "{}", e);
+		std::fs::remove_dir(&transcriber.dir).unwrap();
+	}
+}
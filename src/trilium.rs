@@ -0,0 +1,500 @@
+//! Typed async client for Trilium's [ETAPI](https://github.com/zadam/trilium/wiki/ETAPI).
+
+use chrono::NaiveDate;
+use reqwest::{Client, RequestBuilder, Response};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+	pub note_id: String,
+	pub is_protected: bool,
+	pub title: String,
+	pub r#type: String,
+	pub mime: String,
+	pub date_created: String,
+	pub date_modified: String,
+	pub utc_date_created: String,
+	pub utc_date_modified: String,
+	#[serde(default)]
+	pub parent_note_ids: Vec<String>,
+	#[serde(default)]
+	pub child_note_ids: Vec<String>,
+	#[serde(default)]
+	pub parent_branch_ids: Vec<String>,
+	#[serde(default)]
+	pub child_branch_ids: Vec<String>,
+	/// Owned attributes only, inherited ones (e.g. from a template) are not included.
+	#[serde(default)]
+	pub attributes: Vec<Attribute>,
+}
+
+impl Note {
+	/// Value of the first owned label with this name.
+	pub fn label(&self, name: &str) -> Option<&str> {
+		self.attributes.iter()
+			.find(|x| x.r#type == AttributeType::Label && x.name == name)
+			.map(|x| &*x.value)
+	}
+
+	/// Values of all owned labels with this name.
+	pub fn labels<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+		self.attributes.iter()
+			.filter(move |x| x.r#type == AttributeType::Label && x.name == name)
+			.map(|x| &*x.value)
+	}
+
+	/// Target note ID of the first owned relation with this name.
+	pub fn relation(&self, name: &str) -> Option<&str> {
+		self.attributes.iter()
+			.find(|x| x.r#type == AttributeType::Relation && x.name == name)
+			.map(|x| &*x.value)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+	Label,
+	Relation,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attribute {
+	pub attribute_id: String,
+	pub note_id: String,
+	pub r#type: AttributeType,
+	pub name: String,
+	pub value: String,
+	pub position: i64,
+	pub is_inheritable: bool,
+	pub utc_date_modified: String,
+}
+
+impl Attribute {
+	/// Label that only exists locally, e.g. to add computed values to a note.
+	pub fn label(note_id: &str, name: &str, value: &str) -> Self {
+		Attribute {
+			attribute_id: String::new(),
+			note_id: note_id.to_owned(),
+			r#type: AttributeType::Label,
+			name: name.to_owned(),
+			value: value.to_owned(),
+			position: 0,
+			is_inheritable: false,
+			utc_date_modified: String::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Branch {
+	pub branch_id: String,
+	pub note_id: String,
+	pub parent_note_id: String,
+	pub prefix: Option<String>,
+	pub note_position: i64,
+	pub is_expanded: bool,
+	pub utc_date_modified: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteWithBranch {
+	pub note: Note,
+	pub branch: Branch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateNote {
+	pub parent_note_id: String,
+	pub title: String,
+	pub r#type: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mime: Option<String>,
+	pub content: String,
+}
+
+impl CreateNote {
+	/// HTML text note.
+	pub fn text(parent_note_id: &str, title: &str, content: &str) -> Self {
+		CreateNote {
+			parent_note_id: parent_note_id.to_owned(),
+			title: title.to_owned(),
+			r#type: "text".to_owned(),
+			mime: None,
+			content: content.to_owned(),
+		}
+	}
+
+	/// Image note, its data is uploaded separately (see [`Trilium::put_note_data`]).
+	pub fn image(parent_note_id: &str, title: &str, mime: &str) -> Self {
+		CreateNote {
+			parent_note_id: parent_note_id.to_owned(),
+			title: title.to_owned(),
+			r#type: "image".to_owned(),
+			mime: Some(mime.to_owned()),
+			content: String::new(),
+		}
+	}
+
+	/// File note with the given MIME type.
+	pub fn file(parent_note_id: &str, title: &str, mime: &str, content: &str) -> Self {
+		CreateNote {
+			parent_note_id: parent_note_id.to_owned(),
+			title: title.to_owned(),
+			r#type: "file".to_owned(),
+			mime: Some(mime.to_owned()),
+			content: content.to_owned(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchNote {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub title: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub r#type: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mime: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAttribute {
+	pub note_id: String,
+	pub r#type: AttributeType,
+	pub name: String,
+	pub value: String,
+	pub is_inheritable: bool,
+}
+
+impl CreateAttribute {
+	pub fn label(note_id: &str, name: &str, value: &str) -> Self {
+		CreateAttribute {
+			note_id: note_id.to_owned(),
+			r#type: AttributeType::Label,
+			name: name.to_owned(),
+			value: value.to_owned(),
+			is_inheritable: false,
+		}
+	}
+
+	pub fn relation(note_id: &str, name: &str, target_note_id: &str) -> Self {
+		CreateAttribute {
+			note_id: note_id.to_owned(),
+			r#type: AttributeType::Relation,
+			name: name.to_owned(),
+			value: target_note_id.to_owned(),
+			is_inheritable: false,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchAttribute {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub value: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub position: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateBranch {
+	pub note_id: String,
+	pub parent_note_id: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub note_position: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchBranch {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub note_position: Option<i64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub is_expanded: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginResponse {
+	auth_token: String,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+	results: Vec<Note>,
+}
+
+/// Error body returned by ETAPI for non-2xx responses.
+#[derive(Error, Debug, Deserialize)]
+#[error("{code} ({status}): {message}")]
+pub struct ApiError {
+	pub status: u16,
+	pub code: String,
+	pub message: String,
+}
+
+pub struct Trilium {
+	client: Client,
+	host: String,
+	token: String,
+}
+
+impl Trilium {
+	pub fn new(client: Client, host: &str, token: &str) -> Self {
+		Trilium {
+			client,
+			host: host.trim_end_matches('/').to_owned(),
+			token: token.to_owned(),
+		}
+	}
+
+	/// Log in with the password to get an ETAPI token.
+	pub async fn login(client: Client, host: &str, password: &str) -> Result<Self, crate::Error> {
+		let mut trilium = Trilium::new(client, host, "");
+		// curl /etapi/auth/login -H 'Content-Type: application/json' --data '{"password":"insert_password_here"}'
+		// -> {"authToken":"Vaa6ZmB4bSAp_gPxjJHlG1KKAmEgAJxCuDFN6tGmfyN6uB2rV0/Ho+c="}
+		let resp: LoginResponse = trilium.send(trilium.request(reqwest::Method::POST, "/auth/login")
+			.json(&json!({ "password": password }))).await?.json().await?;
+		trilium.token = resp.auth_token;
+		Ok(trilium)
+	}
+
+	fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+		self.client.request(method, format!("{}/etapi{}", self.host, path))
+			.header("Authorization", &self.token)
+	}
+
+	async fn send(&self, request: RequestBuilder) -> Result<Response, crate::Error> {
+		let resp = request.send().await?;
+		let status = resp.status();
+		if status.is_success() {
+			return Ok(resp);
+		}
+		let text = resp.text().await?;
+		let error = serde_json::from_str(&text).unwrap_or_else(|_| ApiError {
+			status: status.as_u16(),
+			code: String::new(),
+			message: text,
+		});
+		Err(error.into())
+	}
+
+	pub async fn create_note(&self, note: &CreateNote) -> Result<NoteWithBranch, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::POST, "/create-note").json(note)).await?.json().await?)
+	}
+
+	pub async fn get_note(&self, note_id: &str) -> Result<Note, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::GET, &format!("/notes/{}", note_id))).await?.json().await?)
+	}
+
+	pub async fn patch_note(&self, note_id: &str, patch: &PatchNote) -> Result<Note, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::PATCH, &format!("/notes/{}", note_id)).json(patch)).await?.json().await?)
+	}
+
+	pub async fn delete_note(&self, note_id: &str) -> Result<(), crate::Error> {
+		self.send(self.request(reqwest::Method::DELETE, &format!("/notes/{}", note_id))).await?;
+		Ok(())
+	}
+
+	pub async fn get_note_content(&self, note_id: &str) -> Result<String, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::GET, &format!("/notes/{}/content", note_id))).await?.text().await?)
+	}
+
+	pub async fn put_note_content(&self, note_id: &str, content: &str) -> Result<(), crate::Error> {
+		self.send(self.request(reqwest::Method::PUT, &format!("/notes/{}/content", note_id))
+			.header("Content-Type", "text/plain")
+			.body(content.to_owned())).await?;
+		Ok(())
+	}
+
+	/// Replace the content of an image or file note with binary data.
+	pub async fn put_note_data(&self, note_id: &str, data: Vec<u8>) -> Result<(), crate::Error> {
+		self.send(self.request(reqwest::Method::PUT, &format!("/notes/{}/content", note_id))
+			.header("Content-Type", "application/octet-stream")
+			.body(data)).await?;
+		Ok(())
+	}
+
+	pub async fn create_attribute(&self, attribute: &CreateAttribute) -> Result<Attribute, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::POST, "/attributes").json(attribute)).await?.json().await?)
+	}
+
+	pub async fn get_attribute(&self, attribute_id: &str) -> Result<Attribute, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::GET, &format!("/attributes/{}", attribute_id))).await?.json().await?)
+	}
+
+	pub async fn patch_attribute(&self, attribute_id: &str, patch: &PatchAttribute) -> Result<Attribute, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::PATCH, &format!("/attributes/{}", attribute_id)).json(patch)).await?.json().await?)
+	}
+
+	pub async fn delete_attribute(&self, attribute_id: &str) -> Result<(), crate::Error> {
+		self.send(self.request(reqwest::Method::DELETE, &format!("/attributes/{}", attribute_id))).await?;
+		Ok(())
+	}
+
+	pub async fn create_branch(&self, branch: &CreateBranch) -> Result<Branch, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::POST, "/branches").json(branch)).await?.json().await?)
+	}
+
+	pub async fn get_branch(&self, branch_id: &str) -> Result<Branch, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::GET, &format!("/branches/{}", branch_id))).await?.json().await?)
+	}
+
+	pub async fn patch_branch(&self, branch_id: &str, patch: &PatchBranch) -> Result<Branch, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::PATCH, &format!("/branches/{}", branch_id)).json(patch)).await?.json().await?)
+	}
+
+	pub async fn delete_branch(&self, branch_id: &str) -> Result<(), crate::Error> {
+		self.send(self.request(reqwest::Method::DELETE, &format!("/branches/{}", branch_id))).await?;
+		Ok(())
+	}
+
+	/// Day note of the journal, created if missing.
+	pub async fn day_note(&self, date: NaiveDate) -> Result<Note, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::GET, &format!("/calendar/days/{}", date.format("%Y-%m-%d")))).await?.json().await?)
+	}
+
+	/// Inbox note for the given day (the day note unless a `#inbox` note is configured).
+	pub async fn inbox_note(&self, date: NaiveDate) -> Result<Note, crate::Error> {
+		Ok(self.send(self.request(reqwest::Method::GET, &format!("/inbox/{}", date.format("%Y-%m-%d")))).await?.json().await?)
+	}
+
+	/// Full search using Trilium's search syntax, e.g. `#todoDate >= 2021-05-01`.
+	pub async fn search(&self, query: &str) -> Result<Vec<Note>, crate::Error> {
+		let resp: SearchResponse = self.send(self.request(reqwest::Method::GET, "/notes")
+			.query(&[("search", query)])).await?.json().await?;
+		Ok(resp.results)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+	use tokio::task::JoinHandle;
+
+	const NOTE: &str = r#"{
+		"noteId": "abc", "isProtected": false, "title": "task", "type": "text", "mime": "text/html",
+		"dateCreated": "2021-05-01 18:00:00.000+0200", "dateModified": "2021-05-01 18:00:00.000+0200",
+		"utcDateCreated": "2021-05-01 16:00:00.000Z", "utcDateModified": "2021-05-01 16:00:00.000Z",
+		"parentNoteIds": ["root"],
+		"attributes": [{
+			"attributeId": "a1", "noteId": "abc", "type": "label", "name": "todoDate", "value": "2021-05-02",
+			"position": 10, "isInheritable": false, "utcDateModified": "2021-05-01 16:00:00.000Z"
+		}, {
+			"attributeId": "a2", "noteId": "abc", "type": "relation", "name": "template", "value": "tpl",
+			"position": 20, "isInheritable": false, "utcDateModified": "2021-05-01 16:00:00.000Z"
+		}]
+	}"#;
+
+	/// Answers one request with `status` and `body`, returns the base URL and the received request.
+	async fn serve(status: &'static str, body: &'static str) -> (String, JoinHandle<String>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}/", listener.local_addr().unwrap());
+		let request = tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut request = Vec::new();
+			let mut buffer = [0; 1024];
+			// read the head and as much of the body as its content-length says
+			loop {
+				let n = socket.read(&mut buffer).await.unwrap();
+				request.extend_from_slice(&buffer[..n]);
+				let text = String::from_utf8_lossy(&request);
+				if let Some((head, body)) = text.split_once("\r\n\r\n") {
+					let length = head.lines()
+						.find_map(|x| x.to_lowercase().strip_prefix("content-length:").map(|x| x.trim().parse().unwrap()))
+						.unwrap_or(0);
+					if n == 0 || body.len() >= length {
+						break;
+					}
+				}
+			}
+			let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+			socket.write_all(response.as_bytes()).await.unwrap();
+			String::from_utf8(request).unwrap()
+		});
+		(url, request)
+	}
+
+	#[test]
+	fn note() {
+		let note: Note = serde_json::from_str(NOTE).unwrap();
+		assert_eq!((&*note.note_id, &*note.title, &note.parent_note_ids[..]), ("abc", "task", &["root".to_owned()][..]));
+		assert!(note.child_note_ids.is_empty());
+		assert_eq!(note.label("todoDate"), Some("2021-05-02"));
+		assert_eq!(note.label("template"), None);
+		assert_eq!(note.relation("template"), Some("tpl"));
+		assert_eq!(note.attributes[1].position, 20);
+	}
+
+	#[test]
+	fn create_requests() {
+		assert_eq!(serde_json::to_value(CreateNote::text("root", "title", "<p>x</p>")).unwrap(),
+			json!({ "parentNoteId": "root", "title": "title", "type": "text", "content": "<p>x</p>" }));
+		assert_eq!(serde_json::to_value(CreateNote::image("root", "photo", "image/jpeg")).unwrap(),
+			json!({ "parentNoteId": "root", "title": "photo", "type": "image", "mime": "image/jpeg", "content": "" }));
+		assert_eq!(serde_json::to_value(CreateAttribute::relation("abc", "template", "tpl")).unwrap(),
+			json!({ "noteId": "abc", "type": "relation", "name": "template", "value": "tpl", "isInheritable": false }));
+		assert_eq!(serde_json::to_value(PatchNote { title: Some("new".to_owned()), ..PatchNote::default() }).unwrap(),
+			json!({ "title": "new" }));
+	}
+
+	#[tokio::test]
+	async fn get_note() {
+		let (url, request) = serve("200 OK", NOTE).await;
+		let trilium = Trilium::new(Client::new(), &url, "token");
+		assert_eq!(trilium.get_note("abc").await.unwrap().title, "task");
+		let request = request.await.unwrap();
+		assert!(request.starts_with("GET /etapi/notes/abc HTTP/1.1\r\n"), "{}", request);
+		assert!(request.to_lowercase().contains("\r\nauthorization: token\r\n"), "{}", request);
+	}
+
+	#[tokio::test]
+	async fn login() {
+		let (url, request) = serve("201 Created", r#"{"authToken":"secret"}"#).await;
+		let trilium = Trilium::login(Client::new(), &url, "password").await.unwrap();
+		assert_eq!(trilium.token, "secret");
+		let request = request.await.unwrap();
+		assert!(request.starts_with("POST /etapi/auth/login HTTP/1.1\r\n"), "{}", request);
+		assert!(request.ends_with(r#"{"password":"password"}"#), "{}", request);
+	}
+
+	#[tokio::test]
+	async fn api_error() {
+		let (url, _) = serve("404 Not Found", r#"{"status":404,"code":"NOTE_NOT_FOUND","message":"Note 'abc' not found."}"#).await;
+		let trilium = Trilium::new(Client::new(), &url, "token");
+		match trilium.get_note("abc").await {
+			Err(crate::Error::Trilium(e)) => assert_eq!(e.to_string(), "NOTE_NOT_FOUND (404): Note 'abc' not found."),
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+
+	#[tokio::test]
+	async fn raw_error() {
+		// e.g. from a reverse proxy in front of Trilium
+		let (url, _) = serve("502 Bad Gateway", "Bad Gateway").await;
+		let trilium = Trilium::new(Client::new(), &url, "token");
+		match trilium.delete_note("abc").await {
+			Err(crate::Error::Trilium(ApiError { status, code, message })) => assert_eq!((status, &*code, &*message), (502, "", "Bad Gateway")),
+			x => panic!("unexpected {:?}", x),
+		}
+	}
+}